@@ -1,5 +1,27 @@
 use anyhow::Context;
 
+/// Name of the instance made up by the top level gitlab-url and access token
+pub(crate) const DEFAULT_INSTANCE: &str = "gitlab";
+
+/// Environment variable holding the access token of the default instance
+const TOKEN_ENV: &str = "GITLAB_TOKEN";
+
+fn default_gitlab_url() -> String {
+    "https://gitlab.com".to_string()
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_max_pages() -> usize {
+    5
+}
+
+fn default_batch_threshold() -> usize {
+    5
+}
+
 fn default_num_pipelines() -> usize {
     5
 }
@@ -8,6 +30,58 @@ fn default_match_branch() -> regex::Regex {
     regex::Regex::new(".*").unwrap()
 }
 
+/// Lines explaining why a job failed, for cargo, npm, pytest, make and the
+/// runner itself. Earlier patterns take precedence.
+pub(crate) fn default_error_patterns() -> Vec<regex::Regex> {
+    [
+        r"^error\[E\d+\]: ",
+        r"^thread '.*' panicked at",
+        r"^npm ERR! ",
+        r"^E {3}",
+        r"^test .* \.\.\. FAILED$",
+        r"^FAILED ",
+        r"^error: ",
+        r"^make(\[\d+\])?: \*\*\*",
+        r"^ERROR: ",
+    ]
+    .iter()
+    .map(|p| regex::Regex::new(p).unwrap())
+    .collect()
+}
+
+fn de_error_patterns<'de, D>(de: D) -> Result<Vec<regex::Regex>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|p| regex::Regex::new(p).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Statuses in any case, rejecting those GitLab doesn't know
+fn de_statuses<'de, D>(de: D) -> Result<Vec<crate::graphql::PipelineStatusEnum>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    use serde::de::IntoDeserializer;
+    use serde::Deserialize;
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|s| {
+            let de: serde::de::value::StringDeserializer<D::Error> =
+                s.to_uppercase().into_deserializer();
+            match crate::graphql::PipelineStatusEnum::deserialize(de)? {
+                crate::graphql::PipelineStatusEnum::UNKNOWN => Err(serde::de::Error::custom(
+                    format!("unknown pipeline status {}", s),
+                )),
+                status => Ok(status),
+            }
+        })
+        .collect()
+}
+
 fn de_match_branch<'de, D>(de: D) -> Result<regex::Regex, D::Error>
 where
     D: serde::de::Deserializer<'de>,
@@ -33,26 +107,645 @@ where
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Config {
-    pub gitlab_access_token: String,
+    #[serde(default = "default_gitlab_url")]
+    pub gitlab_url: String,
 
+    #[serde(flatten)]
+    pub token: Token,
+
+    /// Additional GitLab instances, the default instance is added by load_config
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+
+    #[serde(default)]
     pub projects: Vec<Project>,
+
+    /// Groups whose projects are discovered at startup and on refresh
+    #[serde(default)]
+    pub groups: Vec<Group>,
+
+    /// Max number of projects fetched at the same time
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// Fetch all projects in one query when there are at least this many, 0 disables
+    #[serde(default = "default_batch_threshold")]
+    pub batch_threshold: usize,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Instance {
+    pub name: String,
+    pub gitlab_url: String,
+    #[serde(flatten)]
+    pub token: Token,
+}
+
+impl Instance {
+    pub(crate) fn access_token(&self) -> anyhow::Result<String> {
+        let env = if self.name == DEFAULT_INSTANCE {
+            Some(TOKEN_ENV)
+        } else {
+            None
+        };
+        self.token
+            .resolve(env)
+            .with_context(|| format!("No access token for instance {}", self.name))
+    }
+}
+
+/// The places an access token can be read from, tried in field order
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Token {
+    pub gitlab_access_token: Option<String>,
+    pub token_file: Option<std::path::PathBuf>,
+    /// Shell command whose stdout is the token, e.g. `pass show gitlab`
+    pub token_command: Option<String>,
+}
+
+impl Token {
+    fn resolve(&self, env: Option<&str>) -> anyhow::Result<String> {
+        let mut tried = Vec::new();
+
+        if let Some(token) = &self.gitlab_access_token {
+            return Ok(token.clone());
+        }
+        tried.push("gitlab-access-token".to_string());
+
+        if let Some(env) = env {
+            match std::env::var(env) {
+                Ok(token) if !token.is_empty() => return Ok(token),
+                _ => tried.push(env.to_string()),
+            }
+        }
+
+        if let Some(token_file) = &self.token_file {
+            match std::fs::read_to_string(token_file) {
+                Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+                Ok(_) => tried.push(format!("token-file {:?} (empty)", token_file)),
+                Err(e) => tried.push(format!("token-file {:?} ({})", token_file, e)),
+            }
+        } else {
+            tried.push("token-file".to_string());
+        }
+
+        if let Some(token_command) = &self.token_command {
+            match std::process::Command::new("sh")
+                .arg("-c")
+                .arg(token_command)
+                .stderr(std::process::Stdio::inherit())
+                .output()
+            {
+                Ok(output) if output.status.success() => {
+                    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                    if !token.is_empty() {
+                        return Ok(token);
+                    }
+                    tried.push(format!("token-command {:?} (empty output)", token_command));
+                }
+                Ok(output) => tried.push(format!(
+                    "token-command {:?} ({})",
+                    token_command, output.status
+                )),
+                Err(e) => tried.push(format!("token-command {:?} ({})", token_command, e)),
+            }
+        } else {
+            tried.push("token-command".to_string());
+        }
+
+        anyhow::bail!("tried {}", tried.join(", "))
+    }
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct Project {
     pub name: String,
+    /// Name of the instance the project lives on, filled in by load_config if not set
+    #[serde(default)]
+    pub instance: String,
+    /// Overrides the instance gitlab-url, filled in by load_config if not set
+    #[serde(default)]
+    pub gitlab_url: String,
     #[serde(deserialize_with = "de_match_branch")]
     #[serde(default = "default_match_branch")]
     pub match_branch_re: regex::Regex,
     #[serde(default = "default_num_pipelines")]
     pub num_pipelines: usize,
+    /// Max number of pages fetched while looking for num-pipelines matches
+    #[serde(default = "default_max_pages")]
+    pub max_pages: usize,
+    #[serde(flatten)]
+    pub filter: PipelineFilter,
+    /// The log of a failed job opens at the first line matching one of these
+    #[serde(deserialize_with = "de_error_patterns")]
+    #[serde(default = "default_error_patterns")]
+    pub error_patterns: Vec<regex::Regex>,
+}
+
+/// Pipelines not matching all of the set filters are left out
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PipelineFilter {
+    /// E.g. FAILED or RUNNING
+    #[serde(default, deserialize_with = "de_statuses")]
+    pub statuses: Vec<crate::graphql::PipelineStatusEnum>,
+    /// How the pipeline was triggered, e.g. push, schedule or merge_request_event
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// The user that triggered the pipeline
+    pub username: Option<String>,
+}
+
+impl PipelineFilter {
+    pub(crate) fn is_match(
+        &self,
+        status: &crate::graphql::PipelineStatusEnum,
+        source: Option<&str>,
+        username: Option<&str>,
+    ) -> bool {
+        if !self.statuses.is_empty() && !self.statuses.contains(status) {
+            return false;
+        }
+        if !self.sources.is_empty() && !source.is_some_and(|s| self.sources.iter().any(|f| f == s))
+        {
+            return false;
+        }
+        match &self.username {
+            Some(u) => username == Some(u.as_str()),
+            None => true,
+        }
+    }
+
+    /// The status to filter on server side, GitLab only takes one
+    pub(crate) fn status(&self) -> Option<crate::graphql::PipelineStatusEnum> {
+        match self.statuses.as_slice() {
+            [status] => Some(status.clone()),
+            _ => None,
+        }
+    }
+
+    /// The source to filter on server side, GitLab only takes one
+    pub(crate) fn source(&self) -> Option<String> {
+        match self.sources.as_slice() {
+            [source] => Some(source.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Project {
+    /// The branch name if match-branch-re only matches a single branch, e.g.
+    /// `^master$` or `^release\-1\.0$`
+    pub(crate) fn exact_branch(&self) -> Option<String> {
+        let mut chars = self
+            .match_branch_re
+            .as_str()
+            .strip_prefix('^')?
+            .strip_suffix('$')?
+            .chars();
+        let mut branch = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                // Escaped punctuation is literal, escaped letters are classes etc.
+                '\\' => match chars.next()? {
+                    c if c.is_ascii_punctuation() => branch.push(c),
+                    _ => return None,
+                },
+                '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => {
+                    return None
+                }
+                c => branch.push(c),
+            }
+        }
+        if branch.is_empty() {
+            None
+        } else {
+            Some(branch)
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Group {
+    pub name: String,
+    /// Name of the instance the group lives on, filled in by load_config if not set
+    #[serde(default)]
+    pub instance: String,
+    /// Overrides the instance gitlab-url, filled in by load_config if not set
+    #[serde(default)]
+    pub gitlab_url: String,
+    #[serde(default)]
+    pub include_subgroups: bool,
+    #[serde(default)]
+    pub include_archived: bool,
+    /// Only projects whose full path matches are included
+    #[serde(deserialize_with = "de_match_branch")]
+    #[serde(default = "default_match_branch")]
+    pub match_project_re: regex::Regex,
+    /// Passed on to the discovered projects
+    #[serde(deserialize_with = "de_match_branch")]
+    #[serde(default = "default_match_branch")]
+    pub match_branch_re: regex::Regex,
+    /// Passed on to the discovered projects
+    #[serde(default = "default_num_pipelines")]
+    pub num_pipelines: usize,
+    /// Passed on to the discovered projects
+    #[serde(default = "default_max_pages")]
+    pub max_pages: usize,
+    /// Passed on to the discovered projects
+    #[serde(flatten)]
+    pub filter: PipelineFilter,
+    /// Passed on to the discovered projects
+    #[serde(deserialize_with = "de_error_patterns")]
+    #[serde(default = "default_error_patterns")]
+    pub error_patterns: Vec<regex::Regex>,
+}
+
+impl Group {
+    /// A project in this group, inheriting the group settings
+    pub(crate) fn project(&self, name: String) -> Project {
+        Project {
+            name,
+            instance: self.instance.clone(),
+            gitlab_url: self.gitlab_url.clone(),
+            match_branch_re: self.match_branch_re.clone(),
+            num_pipelines: self.num_pipelines,
+            max_pages: self.max_pages,
+            filter: self.filter.clone(),
+            error_patterns: self.error_patterns.clone(),
+        }
+    }
 }
 
 pub(crate) fn load_config(cfg_file: &str) -> anyhow::Result<Config> {
     let cfg = std::fs::read(cfg_file).context(cfg_file.to_string())?;
 
-    let config: Config = serde_yaml::from_slice(&cfg)?;
+    parse_config(&cfg)
+}
+
+pub(crate) fn parse_config(cfg: &[u8]) -> anyhow::Result<Config> {
+    let mut config: Config = serde_yaml::from_slice(cfg)?;
+
+    config.gitlab_url = config.gitlab_url.trim_end_matches('/').to_string();
+    config.instances.insert(
+        0,
+        Instance {
+            name: DEFAULT_INSTANCE.to_string(),
+            gitlab_url: config.gitlab_url.clone(),
+            token: config.token.clone(),
+        },
+    );
+
+    for (i, instance) in config.instances.iter_mut().enumerate().skip(1) {
+        instance.gitlab_url = instance.gitlab_url.trim_end_matches('/').to_string();
+        if instance.name == DEFAULT_INSTANCE {
+            anyhow::bail!("Instance #{} can't be named {}", i, DEFAULT_INSTANCE);
+        }
+    }
+
+    for (i, instance) in config.instances.iter().enumerate() {
+        if config.instances[..i]
+            .iter()
+            .any(|o| o.name == instance.name)
+        {
+            anyhow::bail!("Duplicate instance {}", instance.name);
+        }
+    }
+
+    for project in config.projects.iter_mut() {
+        resolve_instance(
+            &config.instances,
+            &project.name,
+            &mut project.instance,
+            &mut project.gitlab_url,
+        )?;
+    }
+
+    for group in config.groups.iter_mut() {
+        resolve_instance(
+            &config.instances,
+            &group.name,
+            &mut group.instance,
+            &mut group.gitlab_url,
+        )?;
+    }
 
     Ok(config)
 }
+
+/// Defaults `instance` and `gitlab_url` of a project or group
+fn resolve_instance(
+    instances: &[Instance],
+    name: &str,
+    instance: &mut String,
+    gitlab_url: &mut String,
+) -> anyhow::Result<()> {
+    if instance.is_empty() {
+        *instance = DEFAULT_INSTANCE.to_string();
+    }
+    let inst = instances
+        .iter()
+        .find(|i| &i.name == instance)
+        .ok_or(anyhow::anyhow!(
+            "Unknown instance {} for {}",
+            instance,
+            name
+        ))?;
+
+    if gitlab_url.is_empty() {
+        *gitlab_url = inst.gitlab_url.clone();
+    } else {
+        *gitlab_url = gitlab_url.trim_end_matches('/').to_string();
+    }
+    Ok(())
+}
+
+impl Config {
+    /// The configured project, or one discovered in a configured group
+    pub(crate) fn project(&self, instance: &str, name: &str) -> Option<Project> {
+        if let Some(project) = self
+            .projects
+            .iter()
+            .find(|p| p.instance == instance && p.name == name)
+        {
+            return Some(project.clone());
+        }
+        self.groups
+            .iter()
+            .find(|g| {
+                g.instance == instance
+                    && name.starts_with(&format!("{}/", g.name))
+                    && g.match_project_re.is_match(name)
+            })
+            .map(|g| g.project(name.to_string()))
+    }
+
+    /// Like project, but projects not in the config get the default settings
+    pub(crate) fn any_project(&self, instance: &str, name: &str) -> anyhow::Result<Project> {
+        if let Some(project) = self.project(instance, name) {
+            return Ok(project);
+        }
+        let instance = self
+            .instances
+            .iter()
+            .find(|i| i.name == instance)
+            .ok_or(anyhow::anyhow!("Unknown instance {}", instance))?;
+        Ok(Project {
+            name: name.to_string(),
+            instance: instance.name.clone(),
+            gitlab_url: instance.gitlab_url.clone(),
+            match_branch_re: default_match_branch(),
+            num_pipelines: default_num_pipelines(),
+            max_pages: default_max_pages(),
+            filter: PipelineFilter::default(),
+            error_patterns: default_error_patterns(),
+        })
+    }
+
+    /// True if any project or group fetches from the instance, unused instances need no token
+    pub(crate) fn instance_in_use(&self, name: &str) -> bool {
+        self.projects.iter().any(|p| p.instance == name)
+            || self.groups.iter().any(|g| g.instance == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn gitlab_url() {
+        let cfg = br#"
+gitlab-access-token: xxx
+gitlab-url: http://localhost:8080/
+projects:
+  - name: a/b
+  - name: c/d
+    gitlab-url: https://gitlab.example.com/
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        assert_eq!(cfg.gitlab_url, "http://localhost:8080");
+        assert_eq!(cfg.projects[0].gitlab_url, "http://localhost:8080");
+        assert_eq!(cfg.projects[1].gitlab_url, "https://gitlab.example.com");
+    }
+
+    #[test]
+    fn default_gitlab_url() {
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a/b
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        assert_eq!(cfg.projects[0].gitlab_url, "https://gitlab.com");
+    }
+
+    #[test]
+    fn instances() {
+        let cfg = br#"
+gitlab-access-token: xxx
+instances:
+  - name: internal
+    gitlab-url: https://gitlab.internal/
+    gitlab-access-token: yyy
+projects:
+  - name: a/b
+  - name: c/d
+    instance: internal
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        assert_eq!(cfg.instances.len(), 2);
+        assert_eq!(cfg.instances[0].name, super::DEFAULT_INSTANCE);
+        assert_eq!(cfg.projects[0].instance, super::DEFAULT_INSTANCE);
+        assert_eq!(cfg.projects[0].gitlab_url, "https://gitlab.com");
+        assert_eq!(cfg.projects[1].instance, "internal");
+        assert_eq!(cfg.projects[1].gitlab_url, "https://gitlab.internal");
+    }
+
+    #[test]
+    fn groups() {
+        let cfg = br#"
+gitlab-access-token: xxx
+groups:
+  - name: my-group
+    include-subgroups: true
+    match-project-re: "^my-group/service-"
+    num-pipelines: 2
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        let group = &cfg.groups[0];
+        assert!(group.include_subgroups);
+        assert!(!group.include_archived);
+        assert!(group.match_project_re.is_match("my-group/service-a"));
+        assert!(!group.match_project_re.is_match("my-group/docs"));
+        let project = group.project("my-group/service-a".to_string());
+        assert_eq!(project.num_pipelines, 2);
+        assert_eq!(project.gitlab_url, "https://gitlab.com");
+        assert!(cfg.instance_in_use(super::DEFAULT_INSTANCE));
+
+        let project = cfg
+            .any_project(super::DEFAULT_INSTANCE, "my-group/service-b")
+            .unwrap();
+        assert_eq!(project.num_pipelines, 2);
+        let project = cfg
+            .any_project(super::DEFAULT_INSTANCE, "other/project")
+            .unwrap();
+        assert_eq!(project.num_pipelines, 5);
+        assert!(cfg.any_project("internal", "other/project").is_err());
+    }
+
+    #[test]
+    fn exact_branch() {
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a
+    match-branch-re: "^master$"
+  - name: b
+    match-branch-re: "master"
+  - name: c
+    match-branch-re: "^release/.*$"
+  - name: d
+  - name: e
+    match-branch-re: "^feature-x$"
+  - name: f
+    match-branch-re: '^release\-1\.0$'
+  - name: g
+    match-branch-re: '^fix\d$'
+  - name: h
+    match-branch-re: '^fix\$'
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        let branches = cfg
+            .projects
+            .iter()
+            .map(|p| p.exact_branch())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            branches,
+            vec![
+                Some("master".to_string()),
+                None,
+                None,
+                None,
+                Some("feature-x".to_string()),
+                Some("release-1.0".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn pipeline_filter() {
+        use crate::graphql::PipelineStatusEnum;
+
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a
+    statuses: [FAILED]
+    sources: [push, schedule]
+    username: alice
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        let filter = &cfg.projects[0].filter;
+        assert_eq!(filter.status(), Some(PipelineStatusEnum::FAILED));
+        assert_eq!(filter.source(), None);
+        assert!(filter.is_match(&PipelineStatusEnum::FAILED, Some("push"), Some("alice")));
+        assert!(!filter.is_match(&PipelineStatusEnum::SUCCESS, Some("push"), Some("alice")));
+        assert!(!filter.is_match(&PipelineStatusEnum::FAILED, Some("api"), Some("alice")));
+        assert!(!filter.is_match(&PipelineStatusEnum::FAILED, None, Some("alice")));
+        assert!(!filter.is_match(&PipelineStatusEnum::FAILED, Some("push"), None));
+
+        assert!(super::PipelineFilter::default().is_match(
+            &PipelineStatusEnum::SUCCESS,
+            None,
+            None
+        ));
+    }
+
+    #[test]
+    fn statuses() {
+        use crate::graphql::PipelineStatusEnum;
+
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a
+    statuses: [failed, Running]
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        assert_eq!(
+            cfg.projects[0].filter.statuses,
+            vec![PipelineStatusEnum::FAILED, PipelineStatusEnum::RUNNING]
+        );
+
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a
+    statuses: [FAILD]
+"#;
+        let err = super::parse_config(cfg).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown pipeline status FAILD"));
+    }
+
+    #[test]
+    fn error_patterns() {
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a
+  - name: b
+    error-patterns: ["^Build failed"]
+"#;
+        let cfg = super::parse_config(cfg).unwrap();
+        let default = &cfg.projects[0].error_patterns;
+        assert!(default
+            .iter()
+            .any(|re| re.is_match("error[E0308]: mismatched types")));
+        assert!(default
+            .iter()
+            .any(|re| re.is_match("npm ERR! code ELIFECYCLE")));
+        assert!(default
+            .iter()
+            .any(|re| re.is_match("make: *** [Makefile:3: all] Error 1")));
+        assert_eq!(cfg.projects[1].error_patterns.len(), 1);
+        assert!(
+            super::parse_config(b"projects:\n  - name: a\n    error-patterns: [\"(\"]\n").is_err()
+        );
+    }
+
+    #[test]
+    fn token_sources() {
+        let token = super::Token {
+            gitlab_access_token: None,
+            token_file: Some("/nonexistent/token".into()),
+            token_command: Some("echo ' abc '".to_string()),
+        };
+        assert_eq!(token.resolve(None).unwrap(), "abc");
+
+        let token = super::Token {
+            token_command: Some("false".to_string()),
+            ..token
+        };
+        let err = token.resolve(None).unwrap_err().to_string();
+        assert!(err.contains("gitlab-access-token"));
+        assert!(err.contains("/nonexistent/token"));
+        assert!(err.contains("token-command"));
+    }
+
+    #[test]
+    fn unknown_instance() {
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a/b
+    instance: internal
+"#;
+        assert!(super::parse_config(cfg).is_err());
+    }
+}
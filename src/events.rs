@@ -1,3 +1,4 @@
+use std::{io::Read, os::unix::io::AsRawFd};
 use termion::{event::Key, input::TermRead};
 
 pub(crate) enum Event {
@@ -5,17 +6,32 @@ pub(crate) enum Event {
     Key(Key),
 }
 
+/// Held while reading keys, and while another program uses the terminal
+static TTY_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Stops reading keys until the guard is dropped, so they go to whatever
+/// program runs in the terminal meanwhile
+pub(crate) fn pause() -> std::sync::MutexGuard<'static, ()> {
+    TTY_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 pub(crate) async fn event_handler(tx: tokio::sync::mpsc::Sender<Event>) {
-    let mut keys = termion::async_stdin().keys();
+    let (keys_tx, keys) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        if let Err(e) = read_keys(keys_tx) {
+            tracing::error!("read_keys - {}", e);
+        }
+    });
+
     let mut interval = tokio::time::interval(std::time::Duration::from_millis(50));
     loop {
-        let res = match keys.next() {
-            None => {
+        let res = match keys.try_recv() {
+            Err(std::sync::mpsc::TryRecvError::Empty) => {
                 interval.tick().await;
                 tx.send(Event::Tick).await
             }
-            Some(Ok(k)) => tx.send(Event::Key(k)).await,
-            Some(Err(_)) => return,
+            Ok(k) => tx.send(Event::Key(k)).await,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => return,
         };
 
         if res.is_err() {
@@ -23,3 +39,37 @@ pub(crate) async fn event_handler(tx: tokio::sync::mpsc::Sender<Event>) {
         }
     }
 }
+
+fn read_keys(tx: std::sync::mpsc::Sender<Key>) -> std::io::Result<()> {
+    let mut tty = termion::get_tty()?;
+    let mut buf = [0; 64];
+    loop {
+        let read = {
+            let _guard = pause();
+            // Poll with a timeout, so that the lock is released now and then
+            let mut fd = libc::pollfd {
+                fd: tty.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `fd` is a single valid pollfd that outlives the call, and
+            // its descriptor is owned by `tty`, which stays open
+            if unsafe { libc::poll(&mut fd, 1, 50) } > 0 {
+                match tty.read(&mut buf)? {
+                    // Readable but empty, the tty hung up
+                    0 => return Ok(()),
+                    read => read,
+                }
+            } else {
+                0
+            }
+        };
+
+        for key in buf[..read].keys() {
+            if tx.send(key?).is_err() {
+                return Ok(());
+            }
+        }
+        std::thread::yield_now();
+    }
+}
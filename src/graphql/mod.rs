@@ -1,62 +1,138 @@
 use graphql_client::GraphQLQuery;
 
-const GQL_URL: &str = "https://gitlab.com/api/graphql";
+fn graphql_url(gitlab_url: &str) -> String {
+    format!("{}/api/graphql", gitlab_url)
+}
+
+/// Turns the top level GraphQL errors of a response into an error
+fn check_errors(errors: Option<Vec<graphql_client::Error>>) -> anyhow::Result<()> {
+    match errors {
+        Some(errors) if !errors.is_empty() => {
+            let errors = errors.iter().map(|e| e.message.clone()).collect::<Vec<_>>();
+            anyhow::bail!("{}", errors.join(", "))
+        }
+        _ => Ok(()),
+    }
+}
 
 type Time = chrono::DateTime<chrono::Local>;
+/// Seconds
+type Duration = f64;
+
+/// Shared by all pipeline queries, see extern_enums
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum PipelineStatusEnum {
+    CANCELED,
+    CREATED,
+    FAILED,
+    MANUAL,
+    PENDING,
+    PREPARING,
+    RUNNING,
+    SCHEDULED,
+    SKIPPED,
+    SUCCESS,
+    WAITING_FOR_RESOURCE,
+    #[serde(other)]
+    UNKNOWN,
+}
+
+impl PipelineStatusEnum {
+    /// Won't change without someone acting on it, MANUAL pipelines wait for a
+    /// job to be played
+    pub(crate) fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            PipelineStatusEnum::SUCCESS
+                | PipelineStatusEnum::FAILED
+                | PipelineStatusEnum::CANCELED
+                | PipelineStatusEnum::SKIPPED
+                | PipelineStatusEnum::MANUAL
+        )
+    }
+}
 
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "graphql/gitlab_schema.graphql",
     query_path = "graphql/project-pipelines.graphql",
+    extern_enums("PipelineStatusEnum"),
     response_derives = "Debug,Clone"
 )]
 struct ProjectPipelines;
 
-#[derive(Clone)]
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/projects-pipelines.graphql",
+    extern_enums("PipelineStatusEnum"),
+    response_derives = "Debug,Clone"
+)]
+struct ProjectsPipelines;
+
+/// Max number of projects in one ProjectsPipelines query
+const BATCH_SIZE: usize = 50;
+
+/// GitLab caps the number of nodes per page at 100
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Clone, serde::Serialize)]
 pub struct PipelineInfo {
+    /// GitLab global id, e.g. gid://gitlab/Ci::Pipeline/1
+    pub id: String,
+    pub instance: String,
+    pub gitlab_url: String,
     pub project_name: String,
     pub pipeline_iid: String,
     pub branch: String,
     pub web_url: String,
-    pub status: project_pipelines::PipelineStatusEnum,
+    pub status: PipelineStatusEnum,
     pub created_at: Time,
+    pub started_at: Option<Time>,
+    pub finished_at: Option<Time>,
+    /// In seconds, only set by GitLab once the pipeline has finished
+    pub duration: Option<i64>,
+    pub sha: String,
+    pub username: Option<String>,
+    pub commit_title: Option<String>,
 }
 
-pub(crate) async fn project_pipelines(
-    client: &reqwest::Client,
-    project: &crate::config::Project,
-) -> anyhow::Result<Vec<PipelineInfo>> {
-    let variables = project_pipelines::Variables {
-        name: project.name.clone(),
-        num: (2 * project.num_pipelines) as _,
-    };
-
-    let response_body =
-        graphql_client::reqwest::post_graphql::<ProjectPipelines, _>(client, GQL_URL, variables)
-            .await?;
-
-    tracing::debug!(?response_body);
-
-    let proj = response_body
-        .data
-        .and_then(|d| d.project)
-        .ok_or(anyhow::anyhow!(
-            "Failed to get project data ({})",
-            project.name
-        ))?;
-
-    let pipelines = proj.pipelines.ok_or(anyhow::anyhow!(
-        "Expected pipeline data for project ({})",
-        project.name
-    ))?;
+#[cfg(test)]
+impl PipelineInfo {
+    /// Pipeline #7 of a/b on master, for tests
+    pub(crate) fn test(status: PipelineStatusEnum) -> Self {
+        use chrono::TimeZone;
+        PipelineInfo {
+            id: "gid://gitlab/Ci::Pipeline/1".to_string(),
+            instance: "gitlab".to_string(),
+            gitlab_url: "https://gitlab.com".to_string(),
+            project_name: "a/b".to_string(),
+            pipeline_iid: "7".to_string(),
+            branch: "master".to_string(),
+            web_url: "https://gitlab.com/a/b/-/pipelines/1".to_string(),
+            status,
+            created_at: chrono::Local
+                .with_ymd_and_hms(2021, 10, 1, 12, 0, 0)
+                .unwrap(),
+            started_at: None,
+            finished_at: None,
+            duration: None,
+            sha: "abc".to_string(),
+            username: None,
+            commit_title: None,
+        }
+    }
+}
 
-    let mut res = Vec::new();
+/// Converts the pipeline nodes of a query into PipelineInfos for `project`.
+/// A macro since every query generates its own, identically shaped, node types.
+macro_rules! pipeline_infos {
+    ($project:expr, $nodes:expr) => {{
+        let project: &crate::config::Project = $project;
+        let mut res = Vec::new();
 
-    for pipeline in pipelines
-        .nodes
-        .ok_or(anyhow::anyhow!("No pipelines ({})", project.name))?
-    {
-        if let Some(pipeline) = pipeline {
+        for pipeline in $nodes.into_iter().flatten() {
             let branch = pipeline.ref_.ok_or(anyhow::anyhow!(
                 "Failed to get branch name ({})",
                 project.name
@@ -65,63 +141,496 @@ pub(crate) async fn project_pipelines(
             if !project.match_branch_re.is_match(&branch) {
                 continue;
             }
+            if !project.filter.is_match(
+                &pipeline.status,
+                pipeline.source.as_deref(),
+                pipeline.user.as_ref().map(|u| u.username.as_str()),
+            ) {
+                continue;
+            }
             res.push(PipelineInfo {
+                id: pipeline.id,
+                instance: project.instance.clone(),
+                gitlab_url: project.gitlab_url.clone(),
                 project_name: project.name.to_string(),
                 pipeline_iid: pipeline.iid.to_string(),
                 created_at: pipeline.created_at,
                 branch,
                 web_url: format!(
-                    "https://www.gitlab.com{}",
+                    "{}{}",
+                    project.gitlab_url,
                     pipeline.path.ok_or(anyhow::anyhow!(
                         "Failed to get pipeline url ({})",
                         project.name
                     ))?
                 ),
                 status: pipeline.status,
+                started_at: pipeline.started_at,
+                finished_at: pipeline.finished_at,
+                duration: pipeline.duration,
+                sha: pipeline.sha,
+                username: pipeline.user.map(|u| u.username),
+                commit_title: pipeline.commit.and_then(|c| c.title),
             });
         }
+        res.truncate(project.num_pipelines);
+        res
+    }};
+}
+
+pub(crate) async fn project_pipelines(
+    client: &reqwest::Client,
+    project: &crate::config::Project,
+) -> anyhow::Result<Vec<PipelineInfo>> {
+    let branch = project.exact_branch();
+    // With server side branch filtering every returned pipeline is a match
+    let page_size = if branch.is_some() {
+        project.num_pipelines
+    } else {
+        2 * project.num_pipelines
+    };
+
+    let mut res = Vec::new();
+    let mut after = None;
+
+    for _ in 0..project.max_pages.max(1) {
+        let variables = project_pipelines::Variables {
+            name: project.name.clone(),
+            num: page_size.min(MAX_PAGE_SIZE) as _,
+            branch: branch.clone(),
+            status: project.filter.status(),
+            source: project.filter.source(),
+            username: project.filter.username.clone(),
+            after: after.take(),
+        };
+
+        let response_body = graphql_client::reqwest::post_graphql::<ProjectPipelines, _>(
+            client,
+            graphql_url(&project.gitlab_url),
+            variables,
+        )
+        .await?;
+
+        tracing::debug!(?response_body);
+
+        let proj = response_body
+            .data
+            .and_then(|d| d.project)
+            .ok_or(anyhow::anyhow!(
+                "Failed to get project data ({})",
+                project.name
+            ))?;
+
+        let pipelines = proj.pipelines.ok_or(anyhow::anyhow!(
+            "Expected pipeline data for project ({})",
+            project.name
+        ))?;
+
+        let nodes = pipelines
+            .nodes
+            .ok_or(anyhow::anyhow!("No pipelines ({})", project.name))?;
+
+        res.append(&mut pipeline_infos!(project, nodes));
+
+        if res.len() >= project.num_pipelines || !pipelines.page_info.has_next_page {
+            break;
+        }
+        after = pipelines.page_info.end_cursor;
+        if after.is_none() {
+            break;
+        }
     }
     res.truncate(project.num_pipelines);
 
     Ok(res)
 }
 
-impl<'a> From<&project_pipelines::PipelineStatusEnum> for tui::widgets::Cell<'a> {
-    fn from(ps: &project_pipelines::PipelineStatusEnum) -> tui::widgets::Cell<'a> {
+/// The IID of the latest pipeline on `git_ref`, a branch or tag name
+pub(crate) async fn latest_pipeline_iid(
+    client: &reqwest::Client,
+    gitlab_url: &str,
+    project_name: &str,
+    git_ref: &str,
+) -> anyhow::Result<String> {
+    let variables = project_pipelines::Variables {
+        name: project_name.to_string(),
+        num: 1,
+        branch: Some(git_ref.to_string()),
+        status: None,
+        source: None,
+        username: None,
+        after: None,
+    };
+
+    let response_body = graphql_client::reqwest::post_graphql::<ProjectPipelines, _>(
+        client,
+        graphql_url(gitlab_url),
+        variables,
+    )
+    .await?;
+
+    tracing::debug!(?response_body);
+
+    check_errors(response_body.errors)?;
+
+    let proj = response_body
+        .data
+        .and_then(|d| d.project)
+        .ok_or(anyhow::anyhow!("No project {}", project_name))?;
+
+    proj.pipelines
+        .and_then(|p| p.nodes)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .next()
+        .map(|p| p.iid)
+        .ok_or(anyhow::anyhow!(
+            "No pipeline on {} in {}",
+            git_ref,
+            project_name
+        ))
+}
+
+/// Pipelines for all `projects` in as few requests as possible, the projects
+/// must share gitlab-url. Projects GitLab doesn't return, or that need more
+/// than one page to find num-pipelines matches, are left out of the result.
+/// Any GraphQL error fails the whole batch.
+pub(crate) async fn projects_pipelines(
+    client: &reqwest::Client,
+    projects: &[crate::config::Project],
+) -> anyhow::Result<std::collections::HashMap<String, Vec<PipelineInfo>>> {
+    let mut res = std::collections::HashMap::new();
+
+    for chunk in projects.chunks(BATCH_SIZE) {
+        let gitlab_url = match chunk.first() {
+            Some(project) => &project.gitlab_url,
+            None => continue,
+        };
+        let variables = projects_pipelines::Variables {
+            names: chunk.iter().map(|p| p.name.clone()).collect(),
+            num: chunk
+                .iter()
+                .map(|p| 2 * p.num_pipelines)
+                .max()
+                .unwrap_or_default()
+                .min(MAX_PAGE_SIZE) as _,
+        };
+
+        let response_body = graphql_client::reqwest::post_graphql::<ProjectsPipelines, _>(
+            client,
+            graphql_url(gitlab_url),
+            variables,
+        )
+        .await?;
+
+        tracing::debug!(?response_body);
+
+        check_errors(response_body.errors)?;
+
+        let nodes = response_body
+            .data
+            .and_then(|d| d.projects)
+            .and_then(|p| p.nodes)
+            .ok_or(anyhow::anyhow!("Failed to get projects data"))?;
+
+        for node in nodes.into_iter().flatten() {
+            let project = match chunk.iter().find(|p| p.name == node.full_path) {
+                Some(project) => project,
+                None => continue,
+            };
+            let pipelines = match node.pipelines {
+                Some(pipelines) => pipelines,
+                None => continue,
+            };
+            let has_next_page = pipelines.page_info.has_next_page;
+            if let Some(nodes) = pipelines.nodes {
+                let infos = pipeline_infos!(project, nodes);
+                if let Some(infos) = batched_infos(project, infos, has_next_page) {
+                    res.insert(project.name.clone(), infos);
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// The pipelines of a project in a batch, or None if num-pipelines matches
+/// weren't found on the first page and it needs a query of its own
+fn batched_infos(
+    project: &crate::config::Project,
+    mut infos: Vec<PipelineInfo>,
+    has_next_page: bool,
+) -> Option<Vec<PipelineInfo>> {
+    if infos.len() < project.num_pipelines && has_next_page {
+        return None;
+    }
+    infos.truncate(project.num_pipelines);
+    Some(infos)
+}
+
+type CiPipelineID = String;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/pipeline-actions.graphql",
+    response_derives = "Debug"
+)]
+struct PipelineRetry;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/pipeline-actions.graphql",
+    response_derives = "Debug"
+)]
+struct PipelineCancel;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/pipeline-actions.graphql",
+    response_derives = "Debug"
+)]
+struct PipelineDestroy;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipelineAction {
+    Retry,
+    Cancel,
+    Destroy,
+}
+
+impl std::fmt::Display for PipelineAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineAction::Retry => f.write_str("Retry"),
+            PipelineAction::Cancel => f.write_str("Cancel"),
+            PipelineAction::Destroy => f.write_str("Delete"),
+        }
+    }
+}
+
+impl PipelineAction {
+    /// For the status line while the mutation runs
+    pub(crate) fn ongoing(&self) -> &'static str {
+        match self {
+            PipelineAction::Retry => "Retrying",
+            PipelineAction::Cancel => "Canceling",
+            PipelineAction::Destroy => "Deleting",
+        }
+    }
+}
+
+/// Runs `action` on `pipeline`, returning the errors reported by the mutation
+pub(crate) async fn pipeline_action(
+    client: &reqwest::Client,
+    pipeline: &PipelineInfo,
+    action: PipelineAction,
+) -> anyhow::Result<Vec<String>> {
+    let url = graphql_url(&pipeline.gitlab_url);
+    let errors = match action {
+        PipelineAction::Retry => {
+            let variables = pipeline_retry::Variables {
+                id: pipeline.id.clone(),
+            };
+            let response_body =
+                graphql_client::reqwest::post_graphql::<PipelineRetry, _>(client, url, variables)
+                    .await?;
+            tracing::debug!(?response_body);
+            check_errors(response_body.errors)?;
+            response_body
+                .data
+                .and_then(|d| d.pipeline_retry)
+                .map(|p| p.errors)
+        }
+        PipelineAction::Cancel => {
+            let variables = pipeline_cancel::Variables {
+                id: pipeline.id.clone(),
+            };
+            let response_body =
+                graphql_client::reqwest::post_graphql::<PipelineCancel, _>(client, url, variables)
+                    .await?;
+            tracing::debug!(?response_body);
+            check_errors(response_body.errors)?;
+            response_body
+                .data
+                .and_then(|d| d.pipeline_cancel)
+                .map(|p| p.errors)
+        }
+        PipelineAction::Destroy => {
+            let variables = pipeline_destroy::Variables {
+                id: pipeline.id.clone(),
+            };
+            let response_body =
+                graphql_client::reqwest::post_graphql::<PipelineDestroy, _>(client, url, variables)
+                    .await?;
+            tracing::debug!(?response_body);
+            check_errors(response_body.errors)?;
+            response_body
+                .data
+                .and_then(|d| d.pipeline_destroy)
+                .map(|p| p.errors)
+        }
+    };
+
+    errors.ok_or(anyhow::anyhow!(
+        "Expected {} result for pipeline {} #{}",
+        action,
+        pipeline.project_name,
+        pipeline.pipeline_iid
+    ))
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/group-projects.graphql",
+    response_derives = "Debug"
+)]
+struct GroupProjects;
+
+/// The projects in `group`, filtered by the group settings and sorted on name
+pub(crate) async fn group_projects(
+    client: &reqwest::Client,
+    group: &crate::config::Group,
+) -> anyhow::Result<Vec<crate::config::Project>> {
+    let mut res = Vec::new();
+    let mut after = None;
+
+    loop {
+        let variables = group_projects::Variables {
+            name: group.name.clone(),
+            include_subgroups: group.include_subgroups,
+            after: after.take(),
+        };
+
+        let response_body = graphql_client::reqwest::post_graphql::<GroupProjects, _>(
+            client,
+            graphql_url(&group.gitlab_url),
+            variables,
+        )
+        .await?;
+
+        tracing::debug!(?response_body);
+
+        let projects = response_body
+            .data
+            .and_then(|d| d.group)
+            .ok_or(anyhow::anyhow!("Failed to get group data ({})", group.name))?
+            .projects;
+
+        for project in projects.nodes.into_iter().flatten().flatten() {
+            if project.archived.unwrap_or(false) && !group.include_archived {
+                continue;
+            }
+            if !group.match_project_re.is_match(&project.full_path) {
+                continue;
+            }
+            res.push(group.project(project.full_path));
+        }
+
+        if !projects.page_info.has_next_page {
+            break;
+        }
+        after = projects.page_info.end_cursor;
+        if after.is_none() {
+            break;
+        }
+    }
+
+    res.sort_by(|p1, p2| p1.name.cmp(&p2.name));
+
+    Ok(res)
+}
+
+impl<'a> From<&PipelineStatusEnum> for tui::widgets::Cell<'a> {
+    fn from(ps: &PipelineStatusEnum) -> tui::widgets::Cell<'a> {
         use tui::style::Style;
         use tui::widgets::Cell;
         let cell = Cell::from(format!("{:?}", ps));
         match ps {
-            project_pipelines::PipelineStatusEnum::SUCCESS => {
+            PipelineStatusEnum::SUCCESS => {
                 cell.style(Style::default().fg(tui::style::Color::Green))
             }
-            project_pipelines::PipelineStatusEnum::FAILED => {
-                cell.style(Style::default().fg(tui::style::Color::Red))
-            }
+            PipelineStatusEnum::FAILED => cell.style(Style::default().fg(tui::style::Color::Red)),
             _ => cell,
         }
     }
 }
 
+/// Shared by all job queries, see extern_enums
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum CiJobStatus {
+    CANCELED,
+    CREATED,
+    FAILED,
+    MANUAL,
+    PENDING,
+    PREPARING,
+    RUNNING,
+    SCHEDULED,
+    SKIPPED,
+    SUCCESS,
+    WAITING_FOR_RESOURCE,
+    #[serde(other)]
+    UNKNOWN,
+}
+
 type JobID = String;
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "graphql/gitlab_schema.graphql",
     query_path = "graphql/pipeline-jobs.graphql",
-    response_derives = "Debug,Clone,Hash,PartialEq,Eq",
+    extern_enums("CiJobStatus", "PipelineStatusEnum"),
+    response_derives = "Debug,Clone",
     variable_derives = "Debug,Display,Clone"
 )]
 struct PipelineJobs;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize)]
 pub struct JobInfo {
+    pub gitlab_url: String,
     pub project_id: String,
     pub id: String,
     pub stage_name: String,
     pub name: String,
-    pub status: pipeline_jobs::CiJobStatus,
+    pub status: CiJobStatus,
+    /// In seconds, only set by GitLab once the job has finished
+    pub duration: Option<i64>,
+    /// In seconds
+    pub queued_duration: Option<f64>,
+    pub started_at: Option<Time>,
+    /// Runner description
+    pub runner: Option<String>,
+    /// Number of earlier, retried, runs of the job
+    pub retries: usize,
+    pub allow_failure: bool,
+}
+
+#[cfg(test)]
+impl JobInfo {
+    /// Job job-`id` in stage test, for tests
+    pub(crate) fn test(id: &str, status: CiJobStatus) -> Self {
+        JobInfo {
+            gitlab_url: "https://gitlab.com".to_string(),
+            project_id: "gid://gitlab/Project/1".to_string(),
+            id: id.to_string(),
+            stage_name: "test".to_string(),
+            name: format!("job-{}", id),
+            status,
+            duration: None,
+            queued_duration: None,
+            started_at: None,
+            runner: None,
+            retries: 0,
+            allow_failure: false,
+        }
+    }
 }
-pub use pipeline_jobs::CiJobStatus;
 
 impl JobInfo {
     pub(crate) fn project_id(&self) -> Option<String> {
@@ -147,17 +656,32 @@ impl JobInfo {
 
 pub async fn pipeline_jobs(
     client: &reqwest::Client,
+    gitlab_url: &str,
     project_name: &str,
     pipeline_id: &str,
 ) -> anyhow::Result<Vec<JobInfo>> {
+    let (_, jobs) = pipeline_status(client, gitlab_url, project_name, pipeline_id).await?;
+    Ok(jobs)
+}
+
+/// The status of a pipeline and its jobs, including downstream pipeline jobs
+pub async fn pipeline_status(
+    client: &reqwest::Client,
+    gitlab_url: &str,
+    project_name: &str,
+    pipeline_id: &str,
+) -> anyhow::Result<(PipelineStatusEnum, Vec<JobInfo>)> {
     let variables = pipeline_jobs::Variables {
         project_name: project_name.to_string(),
         pipeline_id: pipeline_id.to_string(),
     };
 
-    let response_body =
-        graphql_client::reqwest::post_graphql::<PipelineJobs, _>(client, GQL_URL, variables)
-            .await?;
+    let response_body = graphql_client::reqwest::post_graphql::<PipelineJobs, _>(
+        client,
+        graphql_url(gitlab_url),
+        variables,
+    )
+    .await?;
 
     // tracing::info!(?response_body);
 
@@ -178,44 +702,39 @@ pub async fn pipeline_jobs(
         .stages
         .and_then(|s| s.nodes)
         .ok_or(anyhow::Error::msg("No stages"))?;
-    for stage in stages.into_iter() {
-        if let Some(stage) = stage {
-            let mut jobs = stage_jobs(project.id.clone(), stage)?;
-            res.append(&mut jobs);
-        }
+    for stage in stages.into_iter().flatten() {
+        let mut jobs = stage_jobs(gitlab_url, project.id.clone(), stage)?;
+        res.append(&mut jobs);
     }
 
     let downstreams = pipeline.downstream.and_then(|d| d.nodes);
     if let Some(downstreams) = downstreams {
         // tracing::info!(?downstreams);
         for downstream in downstreams.into_iter() {
-            if let Some(stages) = downstream.and_then(|ds| ds.stages) {
-                for nodes in stages.nodes {
-                    for stage_job in nodes {
-                        if let Some(stage_job) = stage_job {
-                            let mut jobs = stage_jobs(project.id.clone(), stage_job)?;
-                            res.append(&mut jobs);
-                        }
-                    }
+            if let Some(nodes) = downstream.and_then(|ds| ds.stages).and_then(|s| s.nodes) {
+                for stage_job in nodes.into_iter().flatten() {
+                    let mut jobs = stage_jobs(gitlab_url, project.id.clone(), stage_job)?;
+                    res.append(&mut jobs);
                 }
             }
         }
     }
 
     res.sort_by(|j1, j2| match (&j1.status, &j2.status) {
-        (pipeline_jobs::CiJobStatus::FAILED, _) => std::cmp::Ordering::Less,
-        (_, pipeline_jobs::CiJobStatus::FAILED) => std::cmp::Ordering::Greater,
-        (pipeline_jobs::CiJobStatus::RUNNING, _) => std::cmp::Ordering::Less,
-        (_, pipeline_jobs::CiJobStatus::RUNNING) => std::cmp::Ordering::Greater,
-        (pipeline_jobs::CiJobStatus::PENDING, _) => std::cmp::Ordering::Less,
-        (_, pipeline_jobs::CiJobStatus::PENDING) => std::cmp::Ordering::Greater,
+        (CiJobStatus::FAILED, _) => std::cmp::Ordering::Less,
+        (_, CiJobStatus::FAILED) => std::cmp::Ordering::Greater,
+        (CiJobStatus::RUNNING, _) => std::cmp::Ordering::Less,
+        (_, CiJobStatus::RUNNING) => std::cmp::Ordering::Greater,
+        (CiJobStatus::PENDING, _) => std::cmp::Ordering::Less,
+        (_, CiJobStatus::PENDING) => std::cmp::Ordering::Greater,
         (_, _) => std::cmp::Ordering::Less,
     });
 
-    Ok(res)
+    Ok((pipeline.status, res))
 }
 
 fn stage_jobs(
+    gitlab_url: &str,
     project_id: String,
     stage_job: pipeline_jobs::StageJobsStagesNodes,
 ) -> anyhow::Result<Vec<JobInfo>> {
@@ -224,40 +743,189 @@ fn stage_jobs(
         .name
         .ok_or(anyhow::Error::msg("Failed to get stage name"))?;
     let jobs = stage_job.jobs.and_then(|j| j.nodes);
+    let mut retries = std::collections::HashMap::new();
     if let Some(jobs) = jobs {
-        for job in jobs {
-            if let Some(job) = job {
-                res.push(JobInfo {
-                    id: job.id.ok_or(anyhow::Error::msg("Failed to get job id"))?,
-                    project_id: project_id.clone(),
-                    stage_name: stage_name.clone(),
-                    name: job
-                        .name
-                        .ok_or(anyhow::Error::msg("Failed to get job name"))?,
-                    status: job
-                        .status
-                        .ok_or(anyhow::Error::msg("Failed to get job status"))?,
-                });
+        for job in jobs.into_iter().flatten() {
+            let name = job
+                .name
+                .ok_or(anyhow::Error::msg("Failed to get job name"))?;
+            // Retried runs are only counted, the latest run is the one shown
+            if job.retried.unwrap_or(false) {
+                *retries.entry(name).or_insert(0) += 1;
+                continue;
             }
+            res.push(JobInfo {
+                gitlab_url: gitlab_url.to_string(),
+                id: job.id.ok_or(anyhow::Error::msg("Failed to get job id"))?,
+                project_id: project_id.clone(),
+                stage_name: stage_name.clone(),
+                name,
+                status: job
+                    .status
+                    .ok_or(anyhow::Error::msg("Failed to get job status"))?,
+                duration: job.duration,
+                queued_duration: job.queued_duration,
+                started_at: job.started_at,
+                runner: job.runner.and_then(|r| r.description),
+                retries: 0,
+                allow_failure: job.allow_failure,
+            });
         }
     }
+    for job in res.iter_mut() {
+        job.retries = retries.get(&job.name).copied().unwrap_or(0);
+    }
     Ok(res)
 }
-impl<'a> From<&pipeline_jobs::CiJobStatus> for tui::widgets::Cell<'a> {
-    fn from(ps: &pipeline_jobs::CiJobStatus) -> tui::widgets::Cell<'a> {
+
+type CiBuildID = String;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/job-actions.graphql",
+    response_derives = "Debug"
+)]
+struct JobRetry;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/job-actions.graphql",
+    response_derives = "Debug"
+)]
+struct JobCancel;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/gitlab_schema.graphql",
+    query_path = "graphql/job-actions.graphql",
+    response_derives = "Debug"
+)]
+struct JobPlay;
+
+#[derive(Clone, Copy, Debug)]
+pub enum JobAction {
+    Retry,
+    Cancel,
+    Play,
+}
+
+impl std::fmt::Display for JobAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobAction::Retry => f.write_str("Retry"),
+            JobAction::Cancel => f.write_str("Cancel"),
+            JobAction::Play => f.write_str("Play"),
+        }
+    }
+}
+
+impl JobAction {
+    /// For the status line while the mutation runs
+    pub(crate) fn ongoing(&self) -> &'static str {
+        match self {
+            JobAction::Retry => "Retrying",
+            JobAction::Cancel => "Canceling",
+            JobAction::Play => "Playing",
+        }
+    }
+}
+
+/// Runs `action` on `job`, returning the errors reported by the mutation
+pub(crate) async fn job_action(
+    client: &reqwest::Client,
+    job: &JobInfo,
+    action: JobAction,
+) -> anyhow::Result<Vec<String>> {
+    let url = graphql_url(&job.gitlab_url);
+    let errors = match action {
+        JobAction::Retry => {
+            let variables = job_retry::Variables { id: job.id.clone() };
+            let response_body =
+                graphql_client::reqwest::post_graphql::<JobRetry, _>(client, url, variables)
+                    .await?;
+            tracing::debug!(?response_body);
+            check_errors(response_body.errors)?;
+            response_body
+                .data
+                .and_then(|d| d.job_retry)
+                .map(|p| p.errors)
+        }
+        JobAction::Cancel => {
+            let variables = job_cancel::Variables { id: job.id.clone() };
+            let response_body =
+                graphql_client::reqwest::post_graphql::<JobCancel, _>(client, url, variables)
+                    .await?;
+            tracing::debug!(?response_body);
+            check_errors(response_body.errors)?;
+            response_body
+                .data
+                .and_then(|d| d.job_cancel)
+                .map(|p| p.errors)
+        }
+        JobAction::Play => {
+            let variables = job_play::Variables { id: job.id.clone() };
+            let response_body =
+                graphql_client::reqwest::post_graphql::<JobPlay, _>(client, url, variables).await?;
+            tracing::debug!(?response_body);
+            check_errors(response_body.errors)?;
+            response_body
+                .data
+                .and_then(|d| d.job_play)
+                .map(|p| p.errors)
+        }
+    };
+
+    errors.ok_or(anyhow::anyhow!(
+        "Expected {} result for job {}",
+        action,
+        job.name
+    ))
+}
+
+impl<'a> From<&CiJobStatus> for tui::widgets::Cell<'a> {
+    fn from(ps: &CiJobStatus) -> tui::widgets::Cell<'a> {
         use tui::style::Style;
         let cell = tui::widgets::Cell::from(format!("{:?}", ps));
         match ps {
-            pipeline_jobs::CiJobStatus::SUCCESS => {
-                cell.style(Style::default().fg(tui::style::Color::Green))
-            }
-            pipeline_jobs::CiJobStatus::FAILED => {
-                cell.style(Style::default().fg(tui::style::Color::Red))
-            }
-            pipeline_jobs::CiJobStatus::RUNNING => {
-                cell.style(Style::default().fg(tui::style::Color::Yellow))
-            }
+            CiJobStatus::SUCCESS => cell.style(Style::default().fg(tui::style::Color::Green)),
+            CiJobStatus::FAILED => cell.style(Style::default().fg(tui::style::Color::Red)),
+            CiJobStatus::RUNNING => cell.style(Style::default().fg(tui::style::Color::Yellow)),
             _ => cell,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{PipelineInfo, PipelineStatusEnum};
+
+    #[test]
+    fn batched_infos() {
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a/b
+    num-pipelines: 2
+"#;
+        let cfg = crate::config::parse_config(cfg).unwrap();
+        let project = &cfg.projects[0];
+        let infos = |n| vec![PipelineInfo::test(PipelineStatusEnum::SUCCESS); n];
+
+        // Too few matches with more pages left falls back to a query of its own
+        assert!(super::batched_infos(project, infos(1), true).is_none());
+        // Too few matches is all there is without more pages
+        assert_eq!(
+            super::batched_infos(project, infos(1), false)
+                .unwrap()
+                .len(),
+            1
+        );
+        // Enough matches are cut to num-pipelines
+        assert_eq!(
+            super::batched_infos(project, infos(3), true).unwrap().len(),
+            2
+        );
+    }
+}
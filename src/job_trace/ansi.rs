@@ -0,0 +1,212 @@
+//! ANSI escape sequences in job logs, only SGR (colors and text attributes)
+//! is rendered, everything else is dropped.
+
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+};
+
+const ESC: char = '\x1b';
+
+/// Length in bytes of the escape sequence at the start of `text`,
+/// the rest of `text` if the sequence is incomplete.
+pub(super) fn escape_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    match bytes.get(1) {
+        // CSI, ends with a byte in 0x40..=0x7e
+        Some(b'[') => bytes[2..]
+            .iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+            .map_or(bytes.len(), |p| p + 3),
+        // OSC, ends with BEL or ESC \
+        Some(b']') => {
+            let mut i = 2;
+            while i < bytes.len() {
+                match bytes[i] {
+                    0x07 => return i + 1,
+                    0x1b if bytes.get(i + 1) == Some(&b'\\') => return i + 2,
+                    _ => i += 1,
+                }
+            }
+            bytes.len()
+        }
+        Some(b) if b.is_ascii() => 2,
+        _ => 1,
+    }
+}
+
+/// The part of a log line left visible in a terminal, GitLab uses `\r` to
+/// overwrite e.g. section markers and progress output. Escape sequences of
+/// the overwritten parts are kept so the style carries over.
+pub(super) fn visible(text: &str) -> String {
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let mut parts = text.split('\r').collect::<Vec<_>>();
+    let last = parts.pop().unwrap_or_default();
+
+    let mut res = String::new();
+    for part in parts {
+        let mut rest = part;
+        while let Some(start) = rest.find(ESC) {
+            let len = escape_len(&rest[start..]);
+            res.push_str(&rest[start..start + len]);
+            rest = &rest[start + len..];
+        }
+    }
+    res.push_str(&last.replace('\t', "    "));
+    res
+}
+
+/// `text` with all escape sequences removed
+pub(super) fn plain(text: &str) -> String {
+    let mut res = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(ESC) {
+        res.push_str(&rest[..start]);
+        rest = &rest[start + escape_len(&rest[start..])..];
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Styled spans for `text`, starting out with `style`. Also returns the style
+/// at the end of `text` to carry over to the text that follows.
+pub(super) fn parse(text: &str, mut style: Style) -> (Spans<'static>, Style) {
+    let mut spans = Vec::new();
+    let mut cur = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(ESC) {
+        cur.push_str(&rest[..start]);
+        let len = escape_len(&rest[start..]);
+        let seq = &rest[start..start + len];
+        if let Some(params) = seq.strip_prefix("\x1b[").and_then(|s| s.strip_suffix('m')) {
+            let new_style = apply_sgr(style, params);
+            if new_style != style && !cur.is_empty() {
+                spans.push(Span::styled(std::mem::take(&mut cur), style));
+            }
+            style = new_style;
+        }
+        rest = &rest[start + len..];
+    }
+    cur.push_str(rest);
+    if !cur.is_empty() {
+        spans.push(Span::styled(cur, style));
+    }
+
+    (Spans::from(spans), style)
+}
+
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+    let codes = params
+        .split(';')
+        .map(|c| c.parse::<u8>().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let mut codes = codes.iter().copied();
+
+    while let Some(code) = codes.next() {
+        style = match code {
+            0 => Style::default(),
+            1 => style.add_modifier(Modifier::BOLD),
+            2 => style.add_modifier(Modifier::DIM),
+            3 => style.add_modifier(Modifier::ITALIC),
+            4 => style.add_modifier(Modifier::UNDERLINED),
+            5 => style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style.add_modifier(Modifier::REVERSED),
+            9 => style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style.remove_modifier(Modifier::ITALIC),
+            24 => style.remove_modifier(Modifier::UNDERLINED),
+            25 => style.remove_modifier(Modifier::SLOW_BLINK),
+            27 => style.remove_modifier(Modifier::REVERSED),
+            29 => style.remove_modifier(Modifier::CROSSED_OUT),
+            30..=37 => style.fg(color(code - 30)),
+            38 => match extended_color(&mut codes) {
+                Some(c) => style.fg(c),
+                None => style,
+            },
+            39 => Style { fg: None, ..style },
+            40..=47 => style.bg(color(code - 40)),
+            48 => match extended_color(&mut codes) {
+                Some(c) => style.bg(c),
+                None => style,
+            },
+            49 => Style { bg: None, ..style },
+            90..=97 => style.fg(color(code - 90 + 8)),
+            100..=107 => style.bg(color(code - 100 + 8)),
+            _ => style,
+        };
+    }
+    style
+}
+
+/// `5;n` or `2;r;g;b` following a 38 or 48
+fn extended_color(codes: &mut impl Iterator<Item = u8>) -> Option<Color> {
+    match codes.next()? {
+        5 => Some(Color::Indexed(codes.next()?)),
+        2 => Some(Color::Rgb(codes.next()?, codes.next()?, codes.next()?)),
+        _ => None,
+    }
+}
+
+fn color(index: u8) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tui::style::{Color, Modifier, Style};
+
+    #[test]
+    fn parse() {
+        let (spans, style) = super::parse("a\x1b[1;31mb\x1b[0Kc\x1b[0m\x1b[32md", Style::default());
+        let red = Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
+        let contents = spans
+            .0
+            .iter()
+            .map(|s| (s.content.to_string(), s.style))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            vec![
+                ("a".to_string(), Style::default()),
+                ("bc".to_string(), red),
+                ("d".to_string(), Style::default().fg(Color::Green)),
+            ]
+        );
+        assert_eq!(style, Style::default().fg(Color::Green));
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(
+            super::plain("\x1b[1;31merror:\x1b[0m x\x1b]8;;u\x07"),
+            "error: x"
+        );
+    }
+
+    #[test]
+    fn visible() {
+        assert_eq!(
+            super::visible("section_start:1:build\r\x1b[0K\x1b[36mBuild\r"),
+            "\x1b[0K\x1b[36mBuild"
+        );
+        assert_eq!(super::visible("\x1b[31m10%\r50%\r100%"), "\x1b[31m100%");
+    }
+}
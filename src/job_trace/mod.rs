@@ -0,0 +1,1160 @@
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::Paragraph,
+    Frame, Terminal,
+};
+use unicode_width::UnicodeWidthChar;
+
+mod ansi;
+mod search;
+mod section;
+
+const JUMP_HEIGHT_DIFF: isize = 3;
+
+/// Columns scrolled by left/right
+const H_SCROLL: usize = 8;
+
+const HELP_TEXT: &str = r#"
+h                 Close  help
+ESC               Exit
+up/down arrow     Scroll
+PgUp/PgDown       Scroll a page
+g/G               Go to top/bottom, G follows the log
+Enter/Space       Expand/collapse section
++/-               Expand/collapse all sections
+/                 Search regex, Tab in the prompt cycles built-in patterns
+n/N               Next/previous match
+e                 Search for common errors
+s                 Save the trace to the current directory
+v                 Open the trace in $PAGER
+E                 Open the trace in $EDITOR
+w                 Wrap lines or scroll them horizontally
+left/right arrow  Scroll horizontally
+l                 Show line numbers
+t                 Show timestamps, if enabled for the runner
+"#;
+
+#[tracing::instrument(skip(terminal, tty, client, key_rx))]
+pub(crate) async fn run<B: Backend>(
+    terminal: &mut Terminal<B>,
+    tty: &crate::ui::Tty,
+    client: &reqwest::Client,
+    key_rx: &mut tokio::sync::mpsc::Receiver<crate::events::Event>,
+    pipeline_id: &str,
+    error_patterns: &[regex::Regex],
+    job: &crate::graphql::JobInfo,
+) -> anyhow::Result<()> {
+    let project_id = job.project_id().unwrap();
+    let job_id = job.job_id().unwrap();
+    let job_uri = format!(
+        "{}/projects/{}/jobs/{}",
+        crate::api_url(&job.gitlab_url),
+        project_id,
+        job_id
+    );
+    let uri = format!("{}/trace", job_uri);
+    let file_name = format!("job-{}-{}-{}.log", project_id, pipeline_id, job_id);
+
+    let mut cur_row: isize = 0;
+    let mut following = true;
+    let mut last_update = chrono::Local::now() - chrono::Duration::seconds(100);
+    let mut dirty = false;
+    let mut logs = Log::default();
+    // Set once the log of a finished job has been fetched, nothing more will come
+    let mut done = false;
+    let mut status = job.status.clone();
+    let mut finished = is_finished(&status);
+    let mut size = Rect::default();
+    let mut help_height_percent = 0;
+    // Columns scrolled to the right, when not wrapping lines
+    let mut h_offset: usize = 0;
+    // The search being typed, and the built-in pattern Tab selects next
+    let mut prompt: Option<String> = None;
+    let mut preset = 0;
+    let mut search: Option<search::Search> = None;
+    let mut message: Option<String> = None;
+    let mut fetch_failed = false;
+    // Failed jobs open at the most likely error, once the whole log is in
+    let mut located = false;
+    let mut error: Option<(usize, String)> = None;
+    logs.set_failed(status == crate::graphql::CiJobStatus::FAILED);
+
+    loop {
+        match key_rx.recv().await {
+            None => return Ok(()),
+            Some(event) => match event {
+                crate::events::Event::Tick => (),
+                crate::events::Event::Key(k) if prompt.is_some() => {
+                    dirty = true;
+                    match k {
+                        termion::event::Key::Char('\n') => {
+                            let pattern = prompt.take().unwrap_or_default();
+                            search = start_search(&pattern, &logs, &mut message);
+                            if let Some(line) = search
+                                .as_mut()
+                                .and_then(|s| s.next(logs.row_line(cur_row as usize)))
+                            {
+                                following = false;
+                                cur_row = logs.show_line(line) as _;
+                            }
+                        }
+                        termion::event::Key::Char('\t') => {
+                            prompt = Some(search::ERROR_PATTERNS[preset].to_string());
+                            preset = (preset + 1) % search::ERROR_PATTERNS.len();
+                        }
+                        termion::event::Key::Char(c) => {
+                            prompt.get_or_insert_with(String::new).push(c)
+                        }
+                        termion::event::Key::Backspace => {
+                            prompt.get_or_insert_with(String::new).pop();
+                        }
+                        termion::event::Key::Esc => prompt = None,
+                        _ => (),
+                    }
+                }
+                crate::events::Event::Key(k) => {
+                    if message.take().is_some() {
+                        dirty = true;
+                    }
+                    match k {
+                        termion::event::Key::Esc => return Ok(()),
+                        termion::event::Key::Up => {
+                            dirty = true;
+                            cur_row -= 1.min(cur_row);
+                            following = false;
+                        }
+                        termion::event::Key::PageUp => {
+                            dirty = true;
+                            let height = terminal.size()?.height as isize - JUMP_HEIGHT_DIFF;
+                            cur_row -= height.min(cur_row);
+                            following = false;
+                        }
+                        termion::event::Key::Down => {
+                            dirty = true;
+                            cur_row += 1;
+                            following = cur_row >= logs.rows.len() as _;
+                        }
+                        termion::event::Key::PageDown => {
+                            dirty = true;
+                            let height = terminal.size()?.height as isize;
+                            cur_row += height - JUMP_HEIGHT_DIFF;
+                            following = cur_row >= logs.rows.len() as _;
+                        }
+                        termion::event::Key::Char('g') => {
+                            dirty = true;
+                            following = false;
+                            cur_row = 0;
+                        }
+                        termion::event::Key::Char('G') => {
+                            dirty = true;
+                            following = true;
+                            let height = terminal.size()?.height as isize;
+                            if logs.rows.len() > height as _ {
+                                cur_row = logs.rows.len() as isize - height;
+                            } else {
+                                cur_row = 0;
+                            }
+                        }
+                        termion::event::Key::Char('\n' | ' ') => {
+                            if let Some(row) = logs.toggle(cur_row as usize) {
+                                dirty = true;
+                                following = false;
+                                cur_row = row as _;
+                            }
+                        }
+                        termion::event::Key::Char(c @ ('+' | '-')) => {
+                            dirty = true;
+                            let row = logs.set_all_collapsed(c == '-', cur_row as usize);
+                            if !following {
+                                cur_row = row as _;
+                            }
+                        }
+                        termion::event::Key::Char('/') => {
+                            dirty = true;
+                            prompt = Some(String::new());
+                            preset = 0;
+                        }
+                        termion::event::Key::Char('e') => {
+                            dirty = true;
+                            let pattern = search::ERROR_PATTERNS
+                                .iter()
+                                .map(|p| regex::escape(p))
+                                .collect::<Vec<_>>()
+                                .join("|");
+                            search = start_search(&pattern, &logs, &mut message);
+                            if let Some(line) = search
+                                .as_mut()
+                                .and_then(|s| s.next(logs.row_line(cur_row as usize)))
+                            {
+                                following = false;
+                                cur_row = logs.show_line(line) as _;
+                            }
+                        }
+                        termion::event::Key::Char(c @ ('n' | 'N')) => {
+                            let line = logs.row_line(cur_row as usize);
+                            let found = search.as_mut().and_then(|s| {
+                                if c == 'n' {
+                                    s.next(line)
+                                } else {
+                                    s.prev(line)
+                                }
+                            });
+                            if let Some(line) = found {
+                                dirty = true;
+                                following = false;
+                                cur_row = logs.show_line(line) as _;
+                            }
+                        }
+                        termion::event::Key::Char('s') => {
+                            dirty = true;
+                            message = Some(match std::fs::write(&file_name, logs.raw()) {
+                                Ok(()) => format!("Saved trace to {}", file_name),
+                                Err(e) => format!("Saving {} failed: {}", file_name, e),
+                            });
+                        }
+                        termion::event::Key::Char(c @ ('v' | 'E')) => {
+                            let (var, default) = if c == 'v' {
+                                ("PAGER", "less -R")
+                            } else {
+                                ("EDITOR", "vi")
+                            };
+                            if let Err(e) = open_external(tty, var, default, &file_name, &logs) {
+                                tracing::error!("open_external {} - {}", var, e);
+                                message = Some(format!("Opening the trace failed: {}", e));
+                            }
+                            terminal.clear()?;
+                            dirty = true;
+                        }
+                        termion::event::Key::Char(c @ ('w' | 'l' | 't')) => {
+                            dirty = true;
+                            match c {
+                                'w' => {
+                                    logs.horizontal = !logs.horizontal;
+                                    h_offset = 0;
+                                }
+                                'l' => logs.line_numbers = !logs.line_numbers,
+                                _ => logs.timestamps = !logs.timestamps,
+                            }
+                            cur_row = logs.rewrap(cur_row as usize) as _;
+                        }
+                        termion::event::Key::Left if logs.horizontal => {
+                            dirty = true;
+                            h_offset = h_offset.saturating_sub(H_SCROLL);
+                        }
+                        termion::event::Key::Right if logs.horizontal => {
+                            dirty = true;
+                            let widest = logs.rows.iter().map(|r| r.width()).max().unwrap_or(0);
+                            h_offset = (h_offset + H_SCROLL).min(widest.saturating_sub(1));
+                        }
+                        termion::event::Key::Char('h') => {
+                            dirty = true;
+                            if help_height_percent > 0 {
+                                help_height_percent = 0;
+                            } else {
+                                help_height_percent = 50;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            },
+        }
+        if !done && (chrono::Local::now() - last_update) > chrono::Duration::seconds(10) {
+            last_update = chrono::Local::now();
+            // Check the status before fetching, so that the last fetch has the whole log
+            if !finished {
+                match job_status(client, &job_uri).await {
+                    Ok(new_status) => {
+                        finished = is_finished(&new_status);
+                        logs.set_failed(new_status == crate::graphql::CiJobStatus::FAILED);
+                        status = new_status;
+                    }
+                    Err(e) => tracing::error!("job status {}: {}", job_uri, e),
+                }
+            }
+            // Errors are shown and the fetch retried on the next poll
+            match fetch_trace(client, &uri, &mut logs).await {
+                Ok(()) => {
+                    if fetch_failed {
+                        fetch_failed = false;
+                        message = None;
+                    }
+                    if let Some(search) = search.as_mut() {
+                        search.update(&logs.lines);
+                    }
+                    done = finished;
+                }
+                Err(e) => {
+                    tracing::error!("job trace {}: {}", uri, e);
+                    fetch_failed = true;
+                    message = Some(format!("Fetching the trace failed: {}", e));
+                }
+            }
+            dirty = true;
+        }
+
+        if terminal.size()? != size {
+            size = terminal.size()?;
+            // One column for the scrollbar
+            cur_row = logs.set_width(size.width.saturating_sub(1) as usize, cur_row as usize) as _;
+            dirty = true;
+        }
+
+        if !located && done && status == crate::graphql::CiJobStatus::FAILED {
+            located = true;
+            if let Some(line) = search::first_error(&logs.lines, error_patterns) {
+                let text = ansi::plain(&ansi::visible(&logs.lines[line]));
+                error = Some((line, text.trim().to_string()));
+                following = false;
+                cur_row = logs
+                    .show_line(line)
+                    .saturating_sub(JUMP_HEIGHT_DIFF as usize) as _;
+                dirty = true;
+            }
+        }
+
+        if dirty {
+            dirty = false;
+
+            let layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![
+                    Constraint::Length(1),
+                    Constraint::Length(if error.is_some() { 1 } else { 0 }),
+                    Constraint::Percentage(100 - help_height_percent),
+                    Constraint::Percentage(help_height_percent),
+                    Constraint::Length(1),
+                ])
+                .split(size);
+            let body = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+                .split(layout[2]);
+
+            let height = body[0].height as usize;
+            if following {
+                let first_line = (logs.rows.len() as i64 - height as i64).max(0) as usize;
+                cur_row = first_line as _;
+            }
+            cur_row = cur_row.min(logs.rows.len().saturating_sub(1) as isize);
+            tracing::debug!(
+                "cur_row: {}, logs: {}, height: {}",
+                cur_row,
+                logs.rows.len(),
+                height
+            );
+
+            terminal.draw(|f| {
+                let first = cur_row as usize;
+                let last = (first + height).min(logs.rows.len());
+                let header = Spans::from(vec![
+                    Span::styled(
+                        format!(" {} ", job.name),
+                        Style::default().add_modifier(Modifier::REVERSED),
+                    ),
+                    Span::raw(format!(
+                        " {} | {:?} | rows {}-{} of {}{} (h for help)",
+                        job.stage_name,
+                        status,
+                        first + 1,
+                        last,
+                        logs.rows.len(),
+                        if following { " | following" } else { "" },
+                    )),
+                ]);
+                f.render_widget(Paragraph::new(header), layout[0]);
+
+                if let Some((line, text)) = &error {
+                    let summary = Span::styled(
+                        format!(" Error at line {}: {}", line + 1, text),
+                        Style::default()
+                            .fg(tui::style::Color::Red)
+                            .add_modifier(Modifier::BOLD),
+                    );
+                    f.render_widget(Paragraph::new(Spans::from(summary)), layout[1]);
+                }
+
+                let rows = (first..last)
+                    .map(|r| {
+                        let mut row = match &search {
+                            Some(search) => search.highlight(&logs.rows[r]),
+                            None => logs.rows[r].clone(),
+                        };
+                        if logs.horizontal {
+                            row = skip_columns(&row, h_offset);
+                        }
+                        if logs.gutter_width() > 0 {
+                            row.0.insert(0, logs.gutter(r));
+                        }
+                        row
+                    })
+                    .collect::<Vec<_>>();
+                f.render_widget(Paragraph::new(rows), body[0]);
+
+                render_scrollbar(f, body[1], first, logs.rows.len());
+
+                f.render_widget(Paragraph::new(HELP_TEXT), layout[3]);
+
+                let status_bar = match (&prompt, &message, &search) {
+                    (Some(prompt), _, _) => format!("/{}", prompt),
+                    (None, Some(message), _) => message.clone(),
+                    (None, None, Some(search)) => search.status(),
+                    (None, None, None) => String::new(),
+                };
+                f.render_widget(Paragraph::new(status_bar), layout[4]);
+            })?;
+        }
+    }
+}
+
+/// Opens a copy of the trace with the program in `$var`, or `default`
+fn open_external(
+    tty: &crate::ui::Tty,
+    var: &str,
+    default: &str,
+    file_name: &str,
+    logs: &Log,
+) -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(file_name);
+    std::fs::write(&path, logs.raw())?;
+    let program = std::env::var(var).unwrap_or_else(|_| default.to_string());
+    // Through sh, $PAGER and $EDITOR may include arguments
+    let status = tty.run_external(
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", program))
+            .arg("sh")
+            .arg(&path),
+    )?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+/// Compiles `pattern` and finds its matches, sets `message` if it's invalid
+fn start_search(pattern: &str, logs: &Log, message: &mut Option<String>) -> Option<search::Search> {
+    *message = None;
+    match search::Search::new(pattern) {
+        Ok(mut search) => {
+            search.update(&logs.lines);
+            Some(search)
+        }
+        Err(e) => {
+            *message = Some(format!("Invalid regex: {}", e));
+            None
+        }
+    }
+}
+
+/// Draws a scrollbar for `total` rows with `first` at the top of `area`
+fn render_scrollbar<B: Backend>(f: &mut Frame<B>, area: Rect, first: usize, total: usize) {
+    let height = area.height as usize;
+    if total <= height || height == 0 {
+        return;
+    }
+    let thumb = (height * height / total).max(1);
+    let start = first * (height - thumb) / (total - height);
+    let bar = (0..height)
+        .map(|i| {
+            if i >= start && i < start + thumb {
+                Spans::from("█")
+            } else {
+                Spans::from("│")
+            }
+        })
+        .collect::<Vec<_>>();
+    f.render_widget(Paragraph::new(bar), area);
+}
+
+fn is_finished(status: &crate::graphql::CiJobStatus) -> bool {
+    use crate::graphql::CiJobStatus;
+    matches!(
+        status,
+        CiJobStatus::SUCCESS
+            | CiJobStatus::FAILED
+            | CiJobStatus::CANCELED
+            | CiJobStatus::SKIPPED
+            | CiJobStatus::MANUAL
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct RestJob {
+    status: String,
+}
+
+async fn job_status(
+    client: &reqwest::Client,
+    job_uri: &str,
+) -> anyhow::Result<crate::graphql::CiJobStatus> {
+    let job: RestJob = client
+        .get(job_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // The REST API uses lower case, GraphQL upper case
+    Ok(serde_json::from_value(serde_json::Value::String(
+        job.status.to_uppercase(),
+    ))?)
+}
+
+/// Appends the part of the trace not yet in `logs`, falls back to fetching
+/// the whole trace if the server doesn't honor the range request.
+async fn fetch_trace(client: &reqwest::Client, uri: &str, logs: &mut Log) -> anyhow::Result<()> {
+    if logs.offset > 0 {
+        let response = client
+            .get(uri)
+            .header(reqwest::header::RANGE, format!("bytes={}-", logs.offset))
+            .send()
+            .await?;
+        match response.status() {
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE => return Ok(()),
+            reqwest::StatusCode::PARTIAL_CONTENT
+                if content_range_start(&response) == Some(logs.offset) =>
+            {
+                let bytes = response.bytes().await?;
+                logs.append(&bytes);
+                return Ok(());
+            }
+            // The range was ignored and the whole trace sent
+            reqwest::StatusCode::OK => {
+                let bytes = response.bytes().await?;
+                logs.clear();
+                logs.append(&bytes);
+                return Ok(());
+            }
+            status => tracing::debug!("unexpected range response: {}", status),
+        }
+    }
+
+    let bytes = client
+        .get(uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    logs.clear();
+    logs.append(&bytes);
+    Ok(())
+}
+
+/// The first byte of a `Content-Range: bytes <start>-<end>/<size>` header
+fn content_range_start(response: &reqwest::Response) -> Option<usize> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// The job log, as received so far, and wrapped to `width` styled rows
+#[derive(Default)]
+struct Log {
+    lines: Vec<String>,
+    /// The last line isn't newline terminated yet
+    partial: bool,
+    /// Start of a utf-8 character split between two fetches
+    pending: Vec<u8>,
+    /// Number of bytes received
+    offset: usize,
+    width: usize,
+    rows: Vec<Spans<'static>>,
+    /// Index in rows of the first row of each line
+    line_rows: Vec<usize>,
+    /// The style in effect at the start of each line, colors carry over lines
+    line_styles: Vec<Style>,
+    sections: Vec<section::Section>,
+    /// The job failed, its failing section is expanded
+    failed: bool,
+    /// GitLab's timestamp prefix of each line, empty if not timestamped
+    prefixes: Vec<String>,
+    /// Lines are scrolled horizontally instead of wrapped
+    horizontal: bool,
+    line_numbers: bool,
+    timestamps: bool,
+    /// Width the rows are wrapped to, width less the gutter
+    wrapped_width: usize,
+}
+
+impl Log {
+    fn clear(&mut self) {
+        *self = Self {
+            width: self.width,
+            horizontal: self.horizontal,
+            line_numbers: self.line_numbers,
+            timestamps: self.timestamps,
+            ..Self::default()
+        };
+    }
+
+    fn push_line(&mut self, line: &str) {
+        let (prefix, text) = line.split_at(timestamp_len(line).unwrap_or(0));
+        self.prefixes.push(prefix.to_string());
+        self.lines.push(text.to_string());
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len();
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            // Keep an incomplete character at the end for the next fetch
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let rest = self.pending.split_off(valid);
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending = rest;
+
+        if text.is_empty() {
+            return;
+        }
+
+        let mut first_changed = self.lines.len();
+        let mut new_lines = text.split('\n');
+        if self.partial {
+            if let (Some(last), Some(first)) = (self.lines.pop(), new_lines.next()) {
+                // The timestamp prefix may have been split too
+                let prefix = self.prefixes.pop().unwrap_or_default();
+                self.push_line(&format!("{}{}{}", prefix, last, first));
+                first_changed -= 1;
+            }
+        }
+        for line in new_lines {
+            self.push_line(line);
+        }
+        // split leaves an empty last element when text ends with a newline
+        self.partial = !text.ends_with('\n');
+        if !self.partial {
+            self.lines.pop();
+            self.prefixes.pop();
+        }
+
+        // Closed sections collapse, so their headers need to be redrawn too
+        let closed = section::parse(&mut self.sections, &self.lines, first_changed);
+        if self.content_width() != self.wrapped_width {
+            // More digits in the line numbers
+            self.wrap_from(0);
+        } else {
+            self.wrap_from(closed.map_or(first_changed, |c| c.min(first_changed)));
+        }
+    }
+
+    /// The trace as received
+    fn raw(&self) -> Vec<u8> {
+        let mut raw = self
+            .prefixes
+            .iter()
+            .zip(self.lines.iter())
+            .map(|(prefix, line)| format!("{}{}", prefix, line))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_bytes();
+        if !self.partial && !self.lines.is_empty() {
+            raw.push(b'\n');
+        }
+        raw.extend_from_slice(&self.pending);
+        raw
+    }
+
+    fn set_failed(&mut self, failed: bool) {
+        if failed != self.failed {
+            self.failed = failed;
+            self.wrap_from(0);
+        }
+    }
+
+    /// The section a failed job most likely failed in. The runner stops at
+    /// the first failing script step, named step_<name>, later sections are
+    /// cleanup.
+    fn failing_section(&self) -> Option<usize> {
+        if !self.failed {
+            return None;
+        }
+        self.sections
+            .iter()
+            .rposition(|s| s.name.starts_with("step_"))
+    }
+
+    /// Sections are collapsed once closed, unless failing or unfolded
+    fn is_collapsed(&self, section: usize, failing: Option<usize>) -> bool {
+        let s = &self.sections[section];
+        s.collapsed
+            .unwrap_or(s.end.is_some() && Some(section) != failing)
+    }
+
+    /// The line shown at `row`
+    fn row_line(&self, row: usize) -> usize {
+        self.line_rows
+            .partition_point(|&r| r <= row)
+            .saturating_sub(1)
+    }
+
+    /// The first row of `line`
+    fn line_row(&self, line: usize) -> usize {
+        self.line_rows.get(line).copied().unwrap_or_default()
+    }
+
+    /// Rewraps the whole log, e.g. after a mode switch, returns the new row
+    /// of the line at `row`
+    fn rewrap(&mut self, row: usize) -> usize {
+        let line = self.row_line(row);
+        self.wrap_from(0);
+        self.line_row(line)
+    }
+
+    /// Columns left of the rows for line numbers and timestamps
+    fn gutter_width(&self) -> usize {
+        let mut width = 0;
+        if self.line_numbers {
+            width += self.lines.len().max(1).to_string().len() + 1;
+        }
+        if self.timestamps {
+            width += "00:00:00 ".len();
+        }
+        width
+    }
+
+    fn content_width(&self) -> usize {
+        self.width.saturating_sub(self.gutter_width())
+    }
+
+    /// Line number and timestamp for `row`, blank unless it starts a line
+    fn gutter(&self, row: usize) -> Span<'static> {
+        let line = self.row_line(row);
+        let first = self.line_rows.get(line) == Some(&row);
+        let mut text = String::new();
+        if self.line_numbers {
+            let digits = self.lines.len().max(1).to_string().len();
+            if first {
+                text.push_str(&format!("{:>1$} ", line + 1, digits));
+            } else {
+                text.push_str(&" ".repeat(digits + 1));
+            }
+        }
+        if self.timestamps {
+            let time = self
+                .prefixes
+                .get(line)
+                .and_then(|p| p.split(' ').next())
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                .filter(|_| first);
+            match time {
+                Some(time) => text.push_str(
+                    &time
+                        .with_timezone(&chrono::Local)
+                        .format("%H:%M:%S ")
+                        .to_string(),
+                ),
+                None => text.push_str("         "),
+            }
+        }
+        Span::styled(text, Style::default().fg(tui::style::Color::DarkGray))
+    }
+
+    /// Expands the sections hiding `line`, returns its row
+    fn show_line(&mut self, line: usize) -> usize {
+        let failing = self.failing_section();
+        let hiding = (0..self.sections.len())
+            .filter(|&s| self.sections[s].start < line && self.sections[s].contains(line))
+            .filter(|&s| self.is_collapsed(s, failing))
+            .collect::<Vec<_>>();
+        if let Some(start) = hiding.iter().map(|&s| self.sections[s].start).min() {
+            for s in hiding {
+                self.sections[s].collapsed = Some(false);
+            }
+            self.wrap_from(start);
+        }
+        self.line_rows.get(line).copied().unwrap_or_default()
+    }
+
+    /// Expands or collapses the innermost section at `row`, returns the
+    /// row of its header.
+    fn toggle(&mut self, row: usize) -> Option<usize> {
+        let line = self.row_line(row);
+        let section = self.sections.iter().rposition(|s| s.contains(line))?;
+        let collapsed = !self.is_collapsed(section, self.failing_section());
+        self.sections[section].collapsed = Some(collapsed);
+        let start = self.sections[section].start;
+        self.wrap_from(start);
+        self.line_rows.get(start).copied()
+    }
+
+    /// Expands or collapses all sections, returns the new row of the line at
+    /// `row`.
+    fn set_all_collapsed(&mut self, collapsed: bool, row: usize) -> usize {
+        for section in self.sections.iter_mut() {
+            section.collapsed = Some(collapsed);
+        }
+        self.rewrap(row)
+    }
+
+    /// Sets the width including the gutter, returns the new row of the line
+    /// at `row`
+    fn set_width(&mut self, width: usize, row: usize) -> usize {
+        self.width = width;
+        self.rewrap(row)
+    }
+
+    fn wrap_from(&mut self, line: usize) {
+        let failing = self.failing_section();
+        // Start over from the header if the line is hidden in a collapsed section
+        let line = (0..self.sections.len())
+            .filter(|&s| self.sections[s].start < line && self.sections[s].contains(line))
+            .filter(|&s| self.is_collapsed(s, failing))
+            .map(|s| self.sections[s].start)
+            .min()
+            .unwrap_or(line);
+
+        let row = self.line_rows.get(line).copied().unwrap_or(self.rows.len());
+        let mut style = self.line_styles.get(line).copied().unwrap_or_default();
+        self.rows.truncate(row);
+        self.line_rows.truncate(line);
+        self.line_styles.truncate(line);
+        self.wrapped_width = self.content_width();
+        if self.width == 0 {
+            return;
+        }
+
+        let mut line = line;
+        while line < self.lines.len() {
+            self.line_rows.push(self.rows.len());
+            self.line_styles.push(style);
+            let mut visible = ansi::visible(&self.lines[line]);
+            let header = self.sections.iter().position(|s| s.start == line);
+
+            if let Some(section) = header {
+                let collapsed = self.is_collapsed(section, failing);
+                let s = &self.sections[section];
+                if is_blank(&visible) {
+                    visible.push_str(&s.name);
+                }
+                visible = format!(
+                    "{}{} {}",
+                    "  ".repeat(s.depth),
+                    if collapsed { '▶' } else { '▼' },
+                    visible
+                );
+                if let Some(duration) = s.duration() {
+                    visible.push_str(&format!(
+                        "\x1b[0m ({})",
+                        crate::pipelines::format_duration(duration)
+                    ));
+                }
+                style = self.push_rows(&visible, style);
+                line += 1;
+
+                if collapsed {
+                    // The section_end line is shown, it may start the next section
+                    let end = self.sections[section].end.unwrap_or(self.lines.len());
+                    while line < end {
+                        self.line_rows.push(self.rows.len());
+                        self.line_styles.push(style);
+                        style = ansi::parse(&ansi::visible(&self.lines[line]), style).1;
+                        line += 1;
+                    }
+                }
+                continue;
+            }
+
+            // Lines with only a section_end marker are hidden, like on GitLab
+            if is_blank(&visible) && self.sections.iter().any(|s| s.end == Some(line)) {
+                style = ansi::parse(&visible, style).1;
+            } else {
+                style = self.push_rows(&visible, style);
+            }
+            line += 1;
+        }
+    }
+
+    /// Wraps and appends `text`, returns the style at its end
+    fn push_rows(&mut self, text: &str, mut style: Style) -> Style {
+        if self.horizontal {
+            let (spans, end_style) = ansi::parse(text, style);
+            self.rows.push(spans);
+            return end_style;
+        }
+        let rows = cut_line(text, self.wrapped_width);
+        if rows.is_empty() {
+            self.rows.push(Spans::default());
+        }
+        for row in rows {
+            let (spans, end_style) = ansi::parse(&row, style);
+            self.rows.push(spans);
+            style = end_style;
+        }
+        style
+    }
+}
+
+/// Length of the `<RFC 3339 time> <stream id><O|E>[+] ` prefix GitLab adds
+/// to each line when timestamps are enabled
+fn timestamp_len(line: &str) -> Option<usize> {
+    if !line.as_bytes().first()?.is_ascii_digit() {
+        return None;
+    }
+    let (time, rest) = line.split_once(' ')?;
+    chrono::DateTime::parse_from_rfc3339(time).ok()?;
+    let stream = rest.split(' ').next()?;
+    let id = stream.strip_suffix('+').unwrap_or(stream);
+    let valid = id.len() == 3
+        && id.is_char_boundary(2)
+        && id[..2].chars().all(|c| c.is_ascii_hexdigit())
+        && matches!(&id[2..], "O" | "E");
+    if !valid {
+        return None;
+    }
+    let len = time.len() + 1 + stream.len();
+    Some(if line[len..].starts_with(' ') {
+        len + 1
+    } else {
+        len
+    })
+}
+
+/// `row` without its first `columns` columns, for horizontal scrolling
+fn skip_columns(row: &Spans<'static>, columns: usize) -> Spans<'static> {
+    let mut skipped = 0;
+    let mut spans = Vec::new();
+    for span in row.0.iter() {
+        if skipped >= columns {
+            spans.push(span.clone());
+            continue;
+        }
+        let mut content = String::new();
+        for c in span.content.chars() {
+            if skipped >= columns {
+                content.push(c);
+                continue;
+            }
+            skipped += c.width().unwrap_or(0);
+            // The right half of a wide character
+            if skipped > columns {
+                content.push(' ');
+            }
+        }
+        if !content.is_empty() {
+            spans.push(Span::styled(content, span.style));
+        }
+    }
+    Spans::from(spans)
+}
+
+/// No visible characters, only escape sequences
+fn is_blank(text: &str) -> bool {
+    ansi::parse(text, Style::default()).0.width() == 0
+}
+
+/// Cuts `text` into rows of at most `width` columns. Escape sequences take
+/// no space and are never split, wide characters take two columns.
+fn cut_line(text: &str, width: usize) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut cur = String::new();
+    let mut cur_width = 0;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\x1b' {
+            let len = ansi::escape_len(rest);
+            cur.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+        let w = c.width().unwrap_or(0);
+        if cur_width + w > width && cur_width > 0 {
+            res.push(std::mem::take(&mut cur));
+            cur_width = 0;
+        }
+        cur.push(c);
+        cur_width += w;
+        rest = &rest[c.len_utf8()..];
+    }
+    if !cur.is_empty() {
+        res.push(cur);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn cut_lines() {
+        let long_text = "abcdefghijklmnop";
+        let cut_lines = super::cut_line(long_text, 5);
+        assert_eq!(cut_lines.as_ref(), vec!["abcde", "fghij", "klmno", "p"]);
+    }
+
+    #[test]
+    fn cut_lines_escapes_and_wide_chars() {
+        let cut_lines = super::cut_line("\x1b[31mabc\x1b[0mde\x1b[1mf", 3);
+        assert_eq!(cut_lines, vec!["\x1b[31mabc\x1b[0m", "de\x1b[1mf"]);
+
+        let cut_lines = super::cut_line("a\u{4e2d}\u{6587}b", 3);
+        assert_eq!(cut_lines, vec!["a\u{4e2d}", "\u{6587}b"]);
+    }
+
+    #[test]
+    fn append_log() {
+        let mut log = super::Log::default();
+        log.set_width(4, 0);
+        log.append(b"abc\nde");
+        assert_eq!(log.lines, vec!["abc", "de"]);
+        log.append(b"fgh\n\nij\xc3");
+        assert_eq!(log.lines, vec!["abc", "defgh", "", "ij"]);
+        log.append(b"\xa5\n");
+        assert_eq!(log.lines, vec!["abc", "defgh", "", "ij\u{e5}"]);
+        assert_eq!(log.offset, 16);
+        let rows = log
+            .rows
+            .iter()
+            .map(|r| {
+                r.0.iter()
+                    .map(|s| s.content.to_string())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec!["abc", "defg", "h", "", "ij\u{e5}"]);
+        assert_eq!(log.line_rows, vec![0, 1, 3, 4]);
+        assert_eq!(log.raw(), b"abc\ndefgh\n\nij\xc3\xa5\n");
+
+        log.append(b"kl\xc3");
+        assert_eq!(log.raw(), b"abc\ndefgh\n\nij\xc3\xa5\nkl\xc3");
+    }
+
+    #[test]
+    fn timestamps() {
+        let line = "2024-02-06T14:50:43.371391Z 00O+ Running with gitlab-runner";
+        let len = super::timestamp_len(line).unwrap();
+        assert_eq!(&line[len..], "Running with gitlab-runner");
+        assert_eq!(super::timestamp_len("2024-02-06T14:50:43Z 01E"), Some(24));
+        assert_eq!(super::timestamp_len("2024 is a year"), None);
+        assert_eq!(super::timestamp_len("2024-02-06T14:50:43Z built"), None);
+
+        let mut log = super::Log::default();
+        log.set_width(20, 0);
+        log.append(b"2024-02-06T14:50:43.371391Z 00O a\n2024-02-06T14:5");
+        log.append(b"0:44Z 00O b\n");
+        assert_eq!(log.lines, vec!["a", "b"]);
+        assert_eq!(
+            log.raw(),
+            b"2024-02-06T14:50:43.371391Z 00O a\n2024-02-06T14:50:44Z 00O b\n"
+        );
+    }
+
+    #[test]
+    fn modes() {
+        let mut log = super::Log::default();
+        log.set_width(5, 0);
+        log.append(b"abcdefgh\n1\n2\n3\n");
+        assert_eq!(row_texts(&log)[..3], ["abcde", "fgh", "1"]);
+
+        // Line 2 stays in view when the rows above it change
+        log.line_numbers = true;
+        assert_eq!(log.rewrap(3), 4);
+        assert_eq!(log.gutter_width(), 2);
+        assert_eq!(row_texts(&log)[..4], ["abc", "def", "gh", "1"]);
+        assert_eq!(log.gutter(0).content, "1 ");
+        assert_eq!(log.gutter(1).content, "  ");
+
+        log.horizontal = true;
+        assert_eq!(log.rewrap(4), 2);
+        assert_eq!(row_texts(&log)[0], "abcdefgh");
+        assert_eq!(
+            super::skip_columns(&log.rows[0], 5),
+            tui::text::Spans::from("fgh")
+        );
+        assert_eq!(
+            super::skip_columns(&tui::text::Spans::from("a\u{4e2d}b"), 2),
+            tui::text::Spans::from(" b")
+        );
+
+        // Line numbers with more digits take more room
+        log.horizontal = false;
+        log.rewrap(0);
+        log.append(b"4\n5\n6\n7\n8\n9\n");
+        assert_eq!(log.gutter_width(), 3);
+        assert_eq!(log.wrapped_width, 2);
+        assert_eq!(log.gutter(0).content, " 1 ");
+    }
+
+    fn row_texts(log: &super::Log) -> Vec<String> {
+        log.rows
+            .iter()
+            .map(|r| r.0.iter().map(|s| s.content.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sections() {
+        let mut log = super::Log::default();
+        log.set_width(40, 0);
+        log.append(
+            b"start\n\x1b[0Ksection_start:10:prepare\r\x1b[0KPreparing\nx\n\
+              \x1b[0Ksection_end:75:prepare\r\x1b[0K\n\
+              \x1b[0Ksection_start:75:step_script\r\x1b[0KScript\ny\n",
+        );
+        assert_eq!(
+            row_texts(&log),
+            vec![
+                "start",
+                "\u{25b6} Preparing (1m05s)",
+                "\u{25bc} Script",
+                "y"
+            ]
+        );
+
+        // Running sections are expanded until closed
+        log.append(b"\x1b[0Ksection_end:80:step_script\r\x1b[0K\ndone\n");
+        assert_eq!(
+            row_texts(&log),
+            vec![
+                "start",
+                "\u{25b6} Preparing (1m05s)",
+                "\u{25b6} Script (5s)",
+                "done"
+            ]
+        );
+
+        // The failing step is expanded
+        log.set_failed(true);
+        assert_eq!(
+            row_texts(&log),
+            vec![
+                "start",
+                "\u{25b6} Preparing (1m05s)",
+                "\u{25bc} Script (5s)",
+                "y",
+                "done"
+            ]
+        );
+
+        assert_eq!(log.toggle(1), Some(1));
+        assert_eq!(
+            row_texts(&log)[1..4],
+            ["\u{25bc} Preparing (1m05s)", "x", "\u{25bc} Script (5s)"]
+        );
+
+        assert_eq!(log.set_all_collapsed(true, 5), 3);
+        assert_eq!(
+            row_texts(&log),
+            vec![
+                "start",
+                "\u{25b6} Preparing (1m05s)",
+                "\u{25b6} Script (5s)",
+                "done"
+            ]
+        );
+    }
+}
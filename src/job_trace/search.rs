@@ -0,0 +1,200 @@
+//! Regex search in job logs
+
+use tui::{
+    style::Color,
+    text::{Span, Spans},
+};
+
+/// Built-in patterns, cycled through with Tab in the search prompt
+pub(super) const ERROR_PATTERNS: &[&str] = &["error:", "FAILED", "panicked at"];
+
+/// The line a failed job most likely failed at, the first line matching the
+/// first pattern that matches anything
+pub(super) fn first_error(lines: &[String], patterns: &[regex::Regex]) -> Option<usize> {
+    let plain = lines
+        .iter()
+        .map(|l| super::ansi::plain(&super::ansi::visible(l)))
+        .collect::<Vec<_>>();
+    patterns
+        .iter()
+        .find_map(|re| plain.iter().position(|l| re.is_match(l)))
+}
+
+pub(super) struct Search {
+    re: regex::Regex,
+    /// Line of each match, a line with several matches is listed once per match
+    matches: Vec<usize>,
+    current: Option<usize>,
+}
+
+impl Search {
+    pub(super) fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            re: regex::Regex::new(pattern)?,
+            matches: Vec::new(),
+            current: None,
+        })
+    }
+
+    /// Finds the matches in `lines`, to be called whenever they change
+    pub(super) fn update(&mut self, lines: &[String]) {
+        self.matches = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(line, text)| {
+                let count = self.re.find_iter(&super::ansi::plain(text)).count();
+                std::iter::repeat_n(line, count)
+            })
+            .collect();
+        self.current = self
+            .current
+            .map(|c| c.min(self.matches.len().saturating_sub(1)))
+            .filter(|_| !self.matches.is_empty());
+    }
+
+    /// Line of the next match, the first one from `line` if none is current
+    pub(super) fn next(&mut self, line: usize) -> Option<usize> {
+        let next = match self.current {
+            Some(current) => (current + 1) % self.matches.len(),
+            None => self.matches.iter().position(|&l| l >= line).unwrap_or(0),
+        };
+        self.select(next)
+    }
+
+    /// Line of the previous match, the last one before `line` if none is current
+    pub(super) fn prev(&mut self, line: usize) -> Option<usize> {
+        let prev = match self.current {
+            Some(current) => (current + self.matches.len() - 1) % self.matches.len(),
+            None => self
+                .matches
+                .iter()
+                .rposition(|&l| l < line)
+                .unwrap_or(self.matches.len().saturating_sub(1)),
+        };
+        self.select(prev)
+    }
+
+    fn select(&mut self, index: usize) -> Option<usize> {
+        let line = *self.matches.get(index)?;
+        self.current = Some(index);
+        Some(line)
+    }
+
+    /// `match i of k` for the status bar
+    pub(super) fn status(&self) -> String {
+        match self.current {
+            _ if self.matches.is_empty() => format!("/{}: no matches", self.re),
+            Some(current) => format!(
+                "/{}: match {} of {}",
+                self.re,
+                current + 1,
+                self.matches.len()
+            ),
+            None => format!("/{}: {} matches", self.re, self.matches.len()),
+        }
+    }
+
+    /// `row` with the matches in it highlighted
+    pub(super) fn highlight(&self, row: &Spans<'static>) -> Spans<'static> {
+        let text = row.0.iter().map(|s| s.content.as_ref()).collect::<String>();
+        let ranges = self
+            .re
+            .find_iter(&text)
+            .filter(|m| !m.range().is_empty())
+            .map(|m| m.range())
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            return row.clone();
+        }
+
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        for span in row.0.iter() {
+            let content = span.content.as_ref();
+            let end = offset + content.len();
+            // Split the span at the match boundaries inside it
+            let mut cuts = vec![offset, end];
+            for r in ranges.iter() {
+                cuts.extend([r.start, r.end].iter().filter(|&&c| c > offset && c < end));
+            }
+            cuts.sort_unstable();
+            for pair in cuts.windows(2) {
+                if pair[0] == pair[1] {
+                    continue;
+                }
+                let matched = ranges
+                    .iter()
+                    .any(|r| r.start <= pair[0] && pair[1] <= r.end);
+                let style = if matched {
+                    span.style.fg(Color::Black).bg(Color::Yellow)
+                } else {
+                    span.style
+                };
+                spans.push(Span::styled(
+                    content[pair[0] - offset..pair[1] - offset].to_string(),
+                    style,
+                ));
+            }
+            offset = end;
+        }
+        Spans::from(spans)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tui::{
+        style::{Color, Style},
+        text::{Span, Spans},
+    };
+
+    #[test]
+    fn navigate() {
+        let lines = ["a", "error: x error: y", "b", "error: z"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        let mut search = super::Search::new("error:").unwrap();
+        search.update(&lines);
+        assert_eq!(search.next(2), Some(3));
+        assert_eq!(search.status(), "/error:: match 3 of 3");
+        assert_eq!(search.next(0), Some(1));
+        assert_eq!(search.prev(0), Some(3));
+        assert_eq!(search.prev(0), Some(1));
+        assert_eq!(search.prev(0), Some(1));
+    }
+
+    #[test]
+    fn first_error() {
+        let lines = [
+            "   Compiling x v0.1.0",
+            "\x1b[0;1;31merror[E0308]\x1b[0m: mismatched types",
+            "error: could not compile `x`",
+            "ERROR: Job failed: exit code 1",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>();
+        let patterns = crate::config::default_error_patterns();
+        assert_eq!(super::first_error(&lines, &patterns), Some(1));
+        assert_eq!(super::first_error(&lines[2..], &patterns), Some(0));
+        assert_eq!(super::first_error(&lines[..1], &patterns), None);
+    }
+
+    #[test]
+    fn highlight() {
+        let search = super::Search::new("or: x").unwrap();
+        let red = Style::default().fg(Color::Red);
+        let row = Spans::from(vec![Span::styled("error:", red), Span::raw(" xy")]);
+        let found = Style::default().fg(Color::Black).bg(Color::Yellow);
+        assert_eq!(
+            search.highlight(&row),
+            Spans::from(vec![
+                Span::styled("err", red),
+                Span::styled("or:", red.patch(found)),
+                Span::styled(" x", found),
+                Span::raw("y"),
+            ])
+        );
+    }
+}
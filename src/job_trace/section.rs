@@ -0,0 +1,120 @@
+//! GitLab log sections, delimited by `section_start:<timestamp>:<name>` and
+//! `section_end:<timestamp>:<name>` markers that the web UI folds.
+
+const MARKER_RE: &str = r"section_(start|end):(\d+):([A-Za-z0-9_.\-]+)(?:\[[^\]]*\])?";
+
+fn marker_re() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| regex::Regex::new(MARKER_RE).unwrap())
+}
+
+#[derive(Debug)]
+pub(super) struct Section {
+    pub name: String,
+    /// Line with the section_start marker, shown as the section header
+    pub start: usize,
+    /// Line with the section_end marker, None while the section is running
+    pub end: Option<usize>,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    /// Number of enclosing sections
+    pub depth: usize,
+    /// Set once folded or unfolded by the user
+    pub collapsed: Option<bool>,
+}
+
+impl Section {
+    pub(super) fn duration(&self) -> Option<i64> {
+        Some(self.ended_at? - self.started_at)
+    }
+
+    pub(super) fn contains(&self, line: usize) -> bool {
+        self.start <= line && self.end.is_none_or(|end| line <= end)
+    }
+}
+
+/// Updates `sections` with the markers in `lines` from `from` on, sections
+/// starting before `from` are kept. Returns the start of the first section
+/// that got closed, its header needs to be redrawn.
+pub(super) fn parse(sections: &mut Vec<Section>, lines: &[String], from: usize) -> Option<usize> {
+    sections.retain(|s| s.start < from);
+    for section in sections.iter_mut() {
+        if section.end.is_some_and(|end| end >= from) {
+            section.end = None;
+            section.ended_at = None;
+        }
+    }
+
+    let re = marker_re();
+    let mut first_closed = None;
+    for (line, text) in lines.iter().enumerate().skip(from) {
+        if !text.contains("section_") {
+            continue;
+        }
+        for cap in re.captures_iter(text) {
+            let timestamp = cap[2].parse().unwrap_or_default();
+            let name = &cap[3];
+            if &cap[1] == "start" {
+                let depth = sections.iter().filter(|s| s.end.is_none()).count();
+                sections.push(Section {
+                    name: name.to_string(),
+                    start: line,
+                    end: None,
+                    started_at: timestamp,
+                    ended_at: None,
+                    depth,
+                    collapsed: None,
+                });
+            } else if let Some(section) = sections
+                .iter_mut()
+                .rev()
+                .find(|s| s.end.is_none() && s.name == name)
+            {
+                section.end = Some(line);
+                section.ended_at = Some(timestamp);
+                first_closed =
+                    Some(first_closed.map_or(section.start, |s: usize| s.min(section.start)));
+            }
+        }
+    }
+    first_closed
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn parse() {
+        let lines = [
+            "\x1b[0Ksection_start:10:prepare[collapsed=true]\r\x1b[0KPreparing",
+            "x",
+            "\x1b[0Ksection_start:11:inner\r\x1b[0KInner",
+            "\x1b[0Ksection_end:12:inner\r\x1b[0K",
+            "\x1b[0Ksection_end:15:prepare\r\x1b[0Ksection_start:15:step_script\r\x1b[0KScript",
+            "y",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>();
+
+        let mut sections = Vec::new();
+        assert_eq!(super::parse(&mut sections, &lines, 0), Some(0));
+        let summary = sections
+            .iter()
+            .map(|s| (s.name.as_str(), s.start, s.end, s.depth, s.duration()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("prepare", 0, Some(4), 0, Some(5)),
+                ("inner", 2, Some(3), 1, Some(1)),
+                ("step_script", 4, None, 0, None),
+            ]
+        );
+
+        // Re-parsing the last lines reopens the sections closed there
+        assert_eq!(super::parse(&mut sections, &lines[..4], 4), None);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].end, None);
+        assert_eq!(sections[1].end, Some(3));
+    }
+}
@@ -6,7 +6,7 @@ use tui::{
     backend::Backend,
     layout::Constraint,
     style::{Modifier, Style},
-    widgets::{Block, Table, TableState},
+    widgets::{Block, Cell, Table, TableState},
     Terminal,
 };
 
@@ -15,14 +15,18 @@ h               Close  help
 ESC             Exit
 up/down arrow   Select job
 Enter           Trace job logs
+r               Retry job
+c               Cancel job
+p               Play manual job
 R               Refresh jobs
 "#;
 
 pub(crate) async fn run<B: Backend>(
     terminal: &mut Terminal<B>,
+    tty: &crate::ui::Tty,
     client: &reqwest::Client,
     key_rx: &mut tokio::sync::mpsc::Receiver<crate::events::Event>,
-    project_name: &str,
+    project: &crate::config::Project,
     pipeline_id: &str,
 ) -> anyhow::Result<()> {
     let mut last_update = chrono::Local::now();
@@ -37,12 +41,42 @@ pub(crate) async fn run<B: Backend>(
     let jobs_updated_tx = std::sync::Arc::new(tokio::sync::Mutex::new(jobs_updated_tx));
 
     let mut jobs_per_type = HashMap::new();
+    let mut confirm: Option<(crate::graphql::JobAction, crate::graphql::JobInfo)> = None;
+    let mut status_line = crate::ui::StatusLine::default();
+    // Job actions run in the background and report back here
+    let (action_tx, mut action_rx) = tokio::sync::mpsc::unbounded_channel();
 
     loop {
         match key_rx.recv().await {
             None => return Ok(()),
             Some(event) => match event {
                 crate::events::Event::Tick => (),
+                crate::events::Event::Key(k) if confirm.is_some() => match k {
+                    termion::event::Key::Char('y') => {
+                        if let Some((action, job)) = confirm.take() {
+                            status_line.set(format!("{} {}...", action.ongoing(), job.name));
+                            let client = client.clone();
+                            let action_tx = action_tx.clone();
+                            tokio::spawn(async move {
+                                let res = crate::graphql::job_action(&client, &job, action).await;
+                                let _ = action_tx.send(match res {
+                                    Ok(errors) if errors.is_empty() => {
+                                        format!("{} {}: ok", action, job.name)
+                                    }
+                                    Ok(errors) => format!(
+                                        "{} {} failed: {}",
+                                        action,
+                                        job.name,
+                                        errors.join(", ")
+                                    ),
+                                    Err(e) => format!("{} {} failed: {}", action, job.name, e),
+                                });
+                            });
+                        }
+                    }
+                    termion::event::Key::Char('n') | termion::event::Key::Esc => confirm = None,
+                    _ => (),
+                },
                 crate::events::Event::Key(k) => match k {
                     termion::event::Key::Esc => return Ok(()),
                     termion::event::Key::Down => {
@@ -78,35 +112,29 @@ pub(crate) async fn run<B: Backend>(
                     }
                     termion::event::Key::Char('\n') => match table_state.selected() {
                         Some(row) if row < jobs.len() => {
-                            crate::job_trace::run(terminal, client, key_rx, &jobs[row]).await?;
-                            // Reset colors from printing job trace
-                            print!("{}", termion::style::Reset);
+                            crate::job_trace::run(
+                                terminal,
+                                tty,
+                                client,
+                                key_rx,
+                                pipeline_id,
+                                &project.error_patterns,
+                                &jobs[row],
+                            )
+                            .await?;
                         }
                         _ => (),
                     },
-                    // termion::event::Key::Char('P') => match table_state.selected() {
-                    //     Some(row) if row < jobs.len() => {
-                    //         let project_id = project_name.replace("/", "%2F");
-                    //         let job_id = jobs[row].job_id().unwrap();
-                    //         let uri = format!(
-                    //             "{}/projects/{}/jobs/{}/play",
-                    //             crate::BASE_URL,
-                    //             project_id,
-                    //             job_id
-                    //         );
-                    //         let res = client.post(uri).send().await;
-                    //         tracing::info!(?res);
-                    //         if let Err(e) = res {
-                    //             tracing::error!(
-                    //                 "play job project_id: {} job_id: {}, error: {}",
-                    //                 project_id,
-                    //                 job_id,
-                    //                 e
-                    //             );
-                    //         }
-                    //     }
-                    //     _ => (),
-                    // },
+                    termion::event::Key::Char(c @ ('r' | 'c' | 'p')) => {
+                        if let Some(job) = table_state.selected().and_then(|row| jobs.get(row)) {
+                            let action = match c {
+                                'r' => crate::graphql::JobAction::Retry,
+                                'c' => crate::graphql::JobAction::Cancel,
+                                _ => crate::graphql::JobAction::Play,
+                            };
+                            confirm = Some((action, job.clone()));
+                        }
+                    }
                     termion::event::Key::Char('R') => {
                         refresh = true;
                     }
@@ -122,6 +150,11 @@ pub(crate) async fn run<B: Backend>(
             },
         }
 
+        while let Ok(text) = action_rx.try_recv() {
+            status_line.set(text);
+            refresh = true;
+        }
+
         if refresh || (chrono::Local::now() - last_update) > chrono::Duration::seconds(30) {
             // last_update = chrono::Local::now();
             refresh = false;
@@ -129,7 +162,8 @@ pub(crate) async fn run<B: Backend>(
             // jobs = crate::graphql::pipeline_jobs(client, project_name, pipeline_id).await?;
             tokio::spawn(update_jobs(
                 client.clone(),
-                project_name.to_string(),
+                project.gitlab_url.clone(),
+                project.name.clone(),
                 pipeline_id.to_string(),
                 jobs_updated_tx.clone(),
             ));
@@ -155,13 +189,7 @@ pub(crate) async fn run<B: Backend>(
         }
 
         terminal.draw(|f| {
-            let rows = jobs.iter().map(|job| {
-                tui::widgets::Row::new(vec![
-                    tui::widgets::Cell::from(job.name.clone()),
-                    (&job.status).into(),
-                    tui::widgets::Cell::from(job.stage_name.clone()),
-                ])
-            });
+            let rows = jobs.iter().map(job_to_row);
 
             let table = Table::new(rows)
                 .block(Block::default().title(format!(
@@ -172,11 +200,27 @@ pub(crate) async fn run<B: Backend>(
                             .get(&crate::graphql::CiJobStatus::PENDING)
                             .unwrap_or(&0)
                     )))
-                .header(tui::widgets::Row::new(vec!["Name", "State", "Stage"]))
+                .header(tui::widgets::Row::new(vec![
+                    "Name",
+                    "State",
+                    "Stage",
+                    "Duration",
+                    "Queued",
+                    "Started At",
+                    "Runner",
+                    "Retries",
+                    "Allow Fail",
+                ]))
                 .widths(&[
-                    Constraint::Percentage(30),
                     Constraint::Percentage(20),
-                    Constraint::Percentage(50),
+                    Constraint::Percentage(10),
+                    Constraint::Percentage(14),
+                    Constraint::Percentage(8),
+                    Constraint::Percentage(8),
+                    Constraint::Percentage(12),
+                    Constraint::Percentage(14),
+                    Constraint::Percentage(6),
+                    Constraint::Percentage(8),
                 ])
                 .highlight_style(
                     Style::default()
@@ -188,6 +232,7 @@ pub(crate) async fn run<B: Backend>(
                 .constraints(vec![
                     Constraint::Percentage(100 - help_height_percent),
                     Constraint::Percentage(help_height_percent),
+                    Constraint::Length(1),
                 ])
                 .direction(tui::layout::Direction::Vertical)
                 .split(f.size());
@@ -195,24 +240,81 @@ pub(crate) async fn run<B: Backend>(
 
             let help = tui::widgets::Paragraph::new(HELP_TEXT);
             f.render_widget(help, main_layout[1]);
+
+            status_line.render(f, main_layout[2]);
+
+            if let Some((action, job)) = &confirm {
+                crate::ui::render_confirm(
+                    f,
+                    main_layout[0],
+                    &format!("{} job {} ({})?", action, job.name, job.stage_name),
+                );
+            }
         })?;
     }
 }
 
+fn job_to_row<'a>(job: &crate::graphql::JobInfo) -> tui::widgets::Row<'a> {
+    use crate::pipelines::format_duration;
+
+    // GitLab only sets the duration once finished, count running jobs up live
+    let duration = match (&job.status, job.started_at, job.duration) {
+        (crate::graphql::CiJobStatus::RUNNING, Some(started_at), _) => Cell::from(format_duration(
+            (chrono::Local::now() - started_at).num_seconds().max(0),
+        ))
+        .style(Style::default().fg(tui::style::Color::Yellow)),
+        (_, _, Some(duration)) => Cell::from(format_duration(duration)),
+        _ => Cell::from("-"),
+    };
+
+    let queued = match job.queued_duration {
+        Some(queued) => format_duration(queued as i64),
+        None => "-".to_string(),
+    };
+
+    let started_at = match job.started_at {
+        Some(started_at) => started_at.format("%b %d %H:%M:%S").to_string(),
+        None => "-".to_string(),
+    };
+
+    let retries = Cell::from(job.retries.to_string());
+    let retries = if job.retries > 0 {
+        retries.style(Style::default().fg(tui::style::Color::Yellow))
+    } else {
+        retries
+    };
+
+    tui::widgets::Row::new(vec![
+        Cell::from(job.name.clone()),
+        (&job.status).into(),
+        Cell::from(job.stage_name.clone()),
+        duration,
+        Cell::from(queued),
+        Cell::from(started_at),
+        Cell::from(job.runner.clone().unwrap_or_default()),
+        retries,
+        Cell::from(if job.allow_failure { "yes" } else { "" }),
+    ])
+}
+
+type JobsTx = std::sync::Arc<
+    tokio::sync::Mutex<
+        tokio::sync::watch::Sender<(
+            chrono::DateTime<chrono::Local>,
+            Vec<crate::graphql::JobInfo>,
+        )>,
+    >,
+>;
+
 async fn update_jobs(
     client: reqwest::Client,
+    gitlab_url: String,
     project_name: String,
     pipeline_id: String,
-    jobs_updated_tx: std::sync::Arc<
-        tokio::sync::Mutex<
-            tokio::sync::watch::Sender<(
-                chrono::DateTime<chrono::Local>,
-                Vec<crate::graphql::JobInfo>,
-            )>,
-        >,
-    >,
+    jobs_updated_tx: JobsTx,
 ) {
-    let jobs = crate::graphql::pipeline_jobs(&client, &project_name, &pipeline_id).await;
+    let jobs =
+        crate::graphql::pipeline_jobs(&client, &gitlab_url, &project_name, &pipeline_id).await;
 
     match jobs {
         Ok(jobs) => {
@@ -1,7 +1,7 @@
 // https://github.com/linkerd/linkerd-await/blob/57590fc9c808216a879f56be2c181d5353b397cc/src/main.rs
 
 use clap::{crate_authors, crate_description, crate_name, crate_version};
-use termion::{raw::IntoRawMode, screen::AlternateScreen};
+use termion::screen::AlternateScreen;
 use tui::{backend::TermionBackend, Terminal};
 
 mod config;
@@ -9,9 +9,46 @@ mod events;
 mod graphql;
 mod job_trace;
 mod jobs;
+mod metrics;
 mod pipelines;
+mod serve;
+mod status;
+mod trigger;
+mod ui;
+mod wait;
+mod watch;
 
-const BASE_URL: &str = "https://www.gitlab.com/api/v4";
+/// REST API root for the GitLab instance at `gitlab_url`
+pub(crate) fn api_url(gitlab_url: &str) -> String {
+    format!("{}/api/v4", gitlab_url)
+}
+
+/// One client per configured instance, keyed on instance name
+pub(crate) type Clients = std::collections::HashMap<String, reqwest::Client>;
+
+fn build_client(access_token: &str) -> anyhow::Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
+
+    let mut private_token = reqwest::header::HeaderValue::from_str(access_token)?;
+    private_token.set_sensitive(true);
+    headers.insert("PRIVATE-TOKEN", private_token);
+
+    let mut auth_header =
+        reqwest::header::HeaderValue::from_str(&format!("Bearer {}", access_token))?;
+    auth_header.set_sensitive(true);
+    headers.insert(reqwest::header::AUTHORIZATION, auth_header);
+
+    headers.insert(
+        reqwest::header::CONTENT_TYPE,
+        reqwest::header::HeaderValue::from_str("application/json")?,
+    );
+
+    let client = reqwest::ClientBuilder::new()
+        .default_headers(headers)
+        // .connection_verbose(true)
+        .build()?;
+    Ok(client)
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -37,40 +74,160 @@ async fn main() -> anyhow::Result<()> {
                 .default_value("config.yaml")
                 .help("Path to config.yaml"),
         )
+        .subcommand(
+            clap::SubCommand::with_name("status")
+                .about("Prints the pipelines once instead of running the TUI")
+                .arg(
+                    clap::Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["table", "json", "yaml", "csv"])
+                        .default_value("table")
+                        .help("Output format"),
+                )
+                .arg(
+                    clap::Arg::with_name("jobs")
+                        .long("jobs")
+                        .help("Include the jobs of each pipeline"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("watch")
+                .about("Prints a line whenever a pipeline or job changes status")
+                .arg(
+                    clap::Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "jsonl"])
+                        .default_value("text")
+                        .help("Output format"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("serve")
+                .about("Serves a web dashboard of the pipelines, and the same data as json on /api/pipelines")
+                .arg(
+                    clap::Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:8080")
+                        .help("Address to listen on"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("serve-metrics")
+                .about("Serves the pipelines as Prometheus metrics on /metrics")
+                .arg(
+                    clap::Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:9185")
+                        .help("Address to listen on"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("wait")
+                .about(
+                    "Waits for a pipeline to finish. Exits with 0 on success, 2 if the \
+                     pipeline failed, was canceled or needs a manual action, 3 on timeout and \
+                     4 if the project, ref or pipeline can't be found. Later API errors are \
+                     retried until the timeout, so they end in 3 too",
+                )
+                .arg(
+                    clap::Arg::with_name("project")
+                        .required(true)
+                        .help("Full path of the project, e.g. group/project"),
+                )
+                .arg(
+                    clap::Arg::with_name("pipeline")
+                        .required(true)
+                        .help("Pipeline IID, or a ref to wait for its latest pipeline"),
+                )
+                .arg(
+                    clap::Arg::with_name("instance")
+                        .long("instance")
+                        .takes_value(true)
+                        .default_value(config::DEFAULT_INSTANCE)
+                        .help("Name of the instance the project lives on"),
+                )
+                .arg(
+                    clap::Arg::with_name("timeout")
+                        .long("timeout")
+                        .takes_value(true)
+                        .default_value("3600")
+                        .help("Seconds to wait"),
+                ),
+        )
         .get_matches();
 
     let cfg_file = matches.value_of("config-file").unwrap();
 
-    let cfg = config::load_config(&cfg_file)?;
+    let cfg = config::load_config(cfg_file)?;
 
-    let mut headers = reqwest::header::HeaderMap::new();
+    let mut clients = Clients::new();
+    for instance in cfg.instances.iter() {
+        if cfg.instance_in_use(&instance.name) {
+            clients.insert(
+                instance.name.clone(),
+                build_client(&instance.access_token()?)?,
+            );
+        }
+    }
 
-    let mut private_token = reqwest::header::HeaderValue::from_str(&cfg.gitlab_access_token)?;
-    private_token.set_sensitive(true);
-    headers.insert("PRIVATE-TOKEN", private_token);
+    if let Some(matches) = matches.subcommand_matches("status") {
+        let format = matches.value_of("format").unwrap().parse()?;
+        return status::run(&clients, &cfg, format, matches.is_present("jobs")).await;
+    }
 
-    let mut auth_header =
-        reqwest::header::HeaderValue::from_str(&format!("Bearer {}", cfg.gitlab_access_token))?;
-    auth_header.set_sensitive(true);
-    headers.insert(reqwest::header::AUTHORIZATION, auth_header);
+    if let Some(matches) = matches.subcommand_matches("watch") {
+        let format = matches.value_of("format").unwrap().parse()?;
+        return watch::run(&clients, &cfg, format).await;
+    }
 
-    headers.insert(
-        reqwest::header::CONTENT_TYPE,
-        reqwest::header::HeaderValue::from_str("application/json")?,
-    );
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        let addr = matches.value_of("listen").unwrap().parse()?;
+        return serve::run(clients, std::sync::Arc::new(cfg), addr).await;
+    }
 
-    let client = reqwest::ClientBuilder::new()
-        .default_headers(headers)
-        // .connection_verbose(true)
-        .build()?;
+    if let Some(matches) = matches.subcommand_matches("serve-metrics") {
+        let addr = matches.value_of("listen").unwrap().parse()?;
+        return metrics::run(clients, std::sync::Arc::new(cfg), addr).await;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("wait") {
+        let instance = matches.value_of("instance").unwrap();
+        let project = cfg.any_project(instance, matches.value_of("project").unwrap())?;
+        let client = match clients.get(instance) {
+            Some(client) => client.clone(),
+            None => {
+                let instance = cfg
+                    .instances
+                    .iter()
+                    .find(|i| i.name == instance)
+                    .ok_or(anyhow::anyhow!("Unknown instance {}", instance))?;
+                build_client(&instance.access_token()?)?
+            }
+        };
+        let timeout = matches.value_of("timeout").unwrap().parse()?;
+        let code = wait::run(
+            &client,
+            &project,
+            matches.value_of("pipeline").unwrap(),
+            std::time::Duration::from_secs(timeout),
+        )
+        .await?;
+        std::process::exit(code);
+    }
 
-    let stdout = std::io::stdout().into_raw_mode()?;
-    let screen = AlternateScreen::from(stdout);
+    let tty = ui::Tty::new()?;
+    let screen = AlternateScreen::from(std::io::stdout());
     let backend = TermionBackend::new(screen);
     let mut terminal = Terminal::new(backend)?;
 
     terminal.clear()?;
-    if let Err(e) = pipelines::run(&mut terminal, client, cfg.projects).await {
+    if let Err(e) = pipelines::run(&mut terminal, &tty, clients, std::sync::Arc::new(cfg)).await {
         tracing::error!(%e);
     }
     Ok(())
@@ -0,0 +1,376 @@
+//! The `serve-metrics` subcommand, serves the pipelines as Prometheus metrics
+
+use crate::graphql::{CiJobStatus, JobInfo, PipelineInfo, PipelineStatusEnum};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+const STATUSES: [PipelineStatusEnum; 11] = [
+    PipelineStatusEnum::CANCELED,
+    PipelineStatusEnum::CREATED,
+    PipelineStatusEnum::FAILED,
+    PipelineStatusEnum::MANUAL,
+    PipelineStatusEnum::PENDING,
+    PipelineStatusEnum::PREPARING,
+    PipelineStatusEnum::RUNNING,
+    PipelineStatusEnum::SCHEDULED,
+    PipelineStatusEnum::SKIPPED,
+    PipelineStatusEnum::SUCCESS,
+    PipelineStatusEnum::WAITING_FOR_RESOURCE,
+];
+
+/// What the last poll found
+#[derive(Default)]
+struct Snapshot {
+    /// The latest pipeline per project and branch, with its jobs unless
+    /// fetching them failed
+    latest: Vec<(PipelineInfo, Option<Vec<JobInfo>>)>,
+    /// Failed fetches per instance and project since startup
+    fetch_errors: BTreeMap<(String, String), u64>,
+}
+
+type SharedSnapshot = std::sync::Arc<tokio::sync::RwLock<Snapshot>>;
+
+pub(crate) async fn run(
+    clients: crate::Clients,
+    cfg: std::sync::Arc<crate::config::Config>,
+    addr: std::net::SocketAddr,
+) -> anyhow::Result<()> {
+    let snapshot = SharedSnapshot::default();
+
+    let poll_snapshot = snapshot.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            crate::pipelines::REFRESH_SECONDS as u64,
+        ));
+        loop {
+            interval.tick().await;
+            update(&clients, &cfg, &poll_snapshot).await;
+        }
+    });
+
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let snapshot = snapshot.clone();
+        async move {
+            Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |req| {
+                serve(req, snapshot.clone())
+            }))
+        }
+    });
+    println!("Serving metrics on http://{}/metrics", addr);
+    hyper::Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn serve(
+    req: hyper::Request<hyper::Body>,
+    snapshot: SharedSnapshot,
+) -> Result<hyper::Response<hyper::Body>, std::convert::Infallible> {
+    let response = if req.method() == hyper::Method::GET && req.uri().path() == "/metrics" {
+        let text = render(&*snapshot.read().await);
+        hyper::Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(hyper::Body::from(text))
+    } else {
+        hyper::Response::builder()
+            .status(hyper::StatusCode::NOT_FOUND)
+            .body(hyper::Body::empty())
+    };
+    Ok(response.expect("valid response"))
+}
+
+/// Fetches the pipelines, and the jobs of the latest pipeline per branch
+/// unless it had already finished. Projects that fail keep their earlier
+/// pipelines.
+async fn update(clients: &crate::Clients, cfg: &crate::config::Config, snapshot: &SharedSnapshot) {
+    let mut latest = Vec::new();
+    let mut fetched_projects = Vec::new();
+    let mut failed = HashSet::new();
+    for (project, pipelines) in crate::pipelines::fetch_pipelines(clients, cfg).await {
+        let key = (project.instance.clone(), project.name.clone());
+        match pipelines {
+            Ok(pipelines) => {
+                // Newest first, so the first pipeline of a branch is the latest
+                let mut branches = HashSet::new();
+                latest.extend(
+                    pipelines
+                        .into_iter()
+                        .filter(|p| branches.insert(p.branch.clone())),
+                );
+            }
+            Err(e) => {
+                eprintln!("{} - {}", project.name, e);
+                failed.insert(key.clone());
+            }
+        }
+        fetched_projects.push(key);
+    }
+
+    // Jobs of finished pipelines only change with the pipeline status
+    let mut cached = snapshot
+        .read()
+        .await
+        .latest
+        .iter()
+        .filter_map(|(p, jobs)| Some((p.id.clone(), (p.status.clone(), jobs.clone()?))))
+        .collect::<HashMap<_, _>>();
+    cached.retain(|id, (status, _)| {
+        status.is_finished() && latest.iter().any(|p| &p.id == id && &p.status == status)
+    });
+    let stale = latest
+        .iter()
+        .filter(|p| !cached.contains_key(&p.id))
+        .cloned()
+        .collect();
+    let mut fetched = crate::pipelines::fetch_jobs(clients, cfg, stale)
+        .await
+        .into_iter()
+        .map(|(pipeline, jobs)| (pipeline.id, jobs))
+        .collect::<HashMap<_, _>>();
+
+    let mut latest = latest
+        .into_iter()
+        .map(|pipeline| {
+            let jobs = match fetched.remove(&pipeline.id) {
+                Some(Ok(jobs)) => Some(jobs),
+                Some(Err(e)) => {
+                    eprintln!(
+                        "{} #{} - {}",
+                        pipeline.project_name, pipeline.pipeline_iid, e
+                    );
+                    None
+                }
+                None => cached.remove(&pipeline.id).map(|(_, jobs)| jobs),
+            };
+            (pipeline, jobs)
+        })
+        .collect::<Vec<_>>();
+
+    let mut snapshot = snapshot.write().await;
+    for key in fetched_projects {
+        let errors = snapshot.fetch_errors.entry(key.clone()).or_insert(0);
+        if failed.contains(&key) {
+            *errors += 1;
+        }
+    }
+    for (pipeline, jobs) in latest.iter() {
+        if jobs.is_none() {
+            let key = (pipeline.instance.clone(), pipeline.project_name.clone());
+            *snapshot.fetch_errors.entry(key).or_insert(0) += 1;
+        }
+    }
+    let kept = std::mem::take(&mut snapshot.latest)
+        .into_iter()
+        .filter(|(p, _)| failed.contains(&(p.instance.clone(), p.project_name.clone())));
+    latest.extend(kept);
+    snapshot.latest = latest;
+}
+
+/// Escapes a label value for the text exposition format
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn pipeline_labels(pipeline: &PipelineInfo) -> String {
+    format!(
+        "instance=\"{}\",project=\"{}\",branch=\"{}\"",
+        label(&pipeline.instance),
+        label(&pipeline.project_name),
+        label(&pipeline.branch)
+    )
+}
+
+/// The snapshot in the Prometheus text exposition format
+fn render(snapshot: &Snapshot) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    };
+
+    metric(
+        "gitlab_pipeline_status",
+        "gauge",
+        "Status of the latest pipeline per project and branch, 1 for the current status",
+        snapshot
+            .latest
+            .iter()
+            .flat_map(|(p, _)| {
+                STATUSES.iter().map(move |status| {
+                    (
+                        format!("{},status=\"{:?}\"", pipeline_labels(p), status),
+                        ((&p.status == status) as u8).to_string(),
+                    )
+                })
+            })
+            .collect(),
+    );
+
+    metric(
+        "gitlab_pipeline_duration_seconds",
+        "gauge",
+        "Duration of the latest finished pipeline per project and branch",
+        snapshot
+            .latest
+            .iter()
+            .filter_map(|(p, _)| Some((pipeline_labels(p), p.duration?.to_string())))
+            .collect(),
+    );
+
+    let count =
+        |jobs: &[JobInfo], status: CiJobStatus| jobs.iter().filter(|j| j.status == status).count();
+    metric(
+        "gitlab_running_jobs",
+        "gauge",
+        "Running jobs in the latest pipeline per project and branch",
+        snapshot
+            .latest
+            .iter()
+            .filter_map(|(p, jobs)| {
+                let jobs = jobs.as_ref()?;
+                Some((
+                    pipeline_labels(p),
+                    count(jobs, CiJobStatus::RUNNING).to_string(),
+                ))
+            })
+            .collect(),
+    );
+    metric(
+        "gitlab_pending_jobs",
+        "gauge",
+        "Pending jobs in the latest pipeline per project and branch",
+        snapshot
+            .latest
+            .iter()
+            .filter_map(|(p, jobs)| {
+                let jobs = jobs.as_ref()?;
+                Some((
+                    pipeline_labels(p),
+                    count(jobs, CiJobStatus::PENDING).to_string(),
+                ))
+            })
+            .collect(),
+    );
+
+    metric(
+        "gitlab_job_duration_seconds",
+        "gauge",
+        "Duration of the finished jobs in the latest pipeline per project and branch",
+        snapshot
+            .latest
+            .iter()
+            .flat_map(|(p, jobs)| {
+                jobs.iter().flatten().filter_map(move |job| {
+                    Some((
+                        // Downstream pipelines often repeat stage and job names
+                        format!(
+                            "{},stage=\"{}\",job=\"{}\",job_id=\"{}\"",
+                            pipeline_labels(p),
+                            label(&job.stage_name),
+                            label(&job.name),
+                            label(job.id.rsplit('/').next().unwrap_or_default())
+                        ),
+                        job.duration?.to_string(),
+                    ))
+                })
+            })
+            .collect(),
+    );
+
+    metric(
+        "gitlab_fetch_errors_total",
+        "counter",
+        "Failed fetches of pipelines or jobs per project",
+        snapshot
+            .fetch_errors
+            .iter()
+            .map(|((instance, project), errors)| {
+                (
+                    format!(
+                        "instance=\"{}\",project=\"{}\"",
+                        label(instance),
+                        label(project)
+                    ),
+                    errors.to_string(),
+                )
+            })
+            .collect(),
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graphql::{CiJobStatus, JobInfo, PipelineInfo, PipelineStatusEnum};
+
+    #[test]
+    fn render() {
+        let pipeline = PipelineInfo {
+            branch: "fix-\"x\"".to_string(),
+            ..PipelineInfo::test(PipelineStatusEnum::RUNNING)
+        };
+        let job = |id: &str, status, duration| JobInfo {
+            duration,
+            ..JobInfo::test(id, status)
+        };
+        let snapshot = super::Snapshot {
+            latest: vec![(
+                pipeline,
+                Some(vec![
+                    job("1", CiJobStatus::SUCCESS, Some(42)),
+                    job("2", CiJobStatus::RUNNING, None),
+                    job("3", CiJobStatus::PENDING, None),
+                    job("4", CiJobStatus::PENDING, None),
+                    // A downstream job with the same stage and name
+                    JobInfo {
+                        name: "job-1".to_string(),
+                        ..job("gid://gitlab/Ci::Build/5", CiJobStatus::SUCCESS, Some(7))
+                    },
+                ]),
+            )],
+            fetch_errors: vec![(("gitlab".to_string(), "a/b".to_string()), 3)]
+                .into_iter()
+                .collect(),
+        };
+
+        let text = super::render(&snapshot);
+        let labels = r#"instance="gitlab",project="a/b",branch="fix-\"x\"""#;
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"# TYPE gitlab_pipeline_status gauge"));
+        assert!(lines.contains(&&*format!(
+            "gitlab_pipeline_status{{{},status=\"RUNNING\"}} 1",
+            labels
+        )));
+        assert!(lines.contains(&&*format!(
+            "gitlab_pipeline_status{{{},status=\"FAILED\"}} 0",
+            labels
+        )));
+        assert!(!text.contains("gitlab_pipeline_duration_seconds{"));
+        assert!(lines.contains(&&*format!("gitlab_running_jobs{{{}}} 1", labels)));
+        assert!(lines.contains(&&*format!("gitlab_pending_jobs{{{}}} 2", labels)));
+        assert!(lines.contains(&&*format!(
+            "gitlab_job_duration_seconds{{{},stage=\"test\",job=\"job-1\",job_id=\"1\"}} 42",
+            labels
+        )));
+        assert!(lines.contains(&&*format!(
+            "gitlab_job_duration_seconds{{{},stage=\"test\",job=\"job-1\",job_id=\"5\"}} 7",
+            labels
+        )));
+        let series = lines
+            .iter()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.rsplit_once(' ').unwrap().0)
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(
+            series.len(),
+            lines.iter().filter(|l| !l.starts_with('#')).count()
+        );
+        assert!(lines.contains(&"gitlab_fetch_errors_total{instance=\"gitlab\",project=\"a/b\"} 3"));
+    }
+}
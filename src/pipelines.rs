@@ -11,13 +11,29 @@ h               Close  help
 ESC             Exit
 up/down arrow   Select pipeline
 Enter           List pipleline jobs
+r               Retry pipeline
+c               Cancel pipeline
+D               Delete pipeline
+N               New pipeline
 R               Refresh pipelines
 "#;
 
+/// Seconds between fetches of the pipelines
+pub(crate) const REFRESH_SECONDS: i64 = 30;
+
+/// Requests spawned from the key handlers report back with these
+enum Done {
+    /// Status line text, and the instance and name of the project to refetch
+    Action(String, String, String),
+    /// Where a pipeline was created, and its iid
+    Created(crate::trigger::Target, anyhow::Result<String>),
+}
+
 pub(crate) async fn run<B: Backend>(
     terminal: &mut Terminal<B>,
-    client: reqwest::Client,
-    projects: Vec<crate::config::Project>,
+    tty: &crate::ui::Tty,
+    clients: crate::Clients,
+    cfg: std::sync::Arc<crate::config::Config>,
 ) -> anyhow::Result<()> {
     let (key_tx, mut key_rx) = tokio::sync::mpsc::channel(10);
     tokio::spawn(crate::events::event_handler(key_tx));
@@ -32,11 +48,97 @@ pub(crate) async fn run<B: Backend>(
 
     let (pipe_tx, mut pipe_rx) = tokio::sync::watch::channel((chrono::Local::now(), Vec::new()));
     let pipe_tx = std::sync::Arc::new(tokio::sync::Mutex::new(pipe_tx));
+
+    // Deleting asks twice, the flag is set once the first question is answered
+    let mut confirm: Option<(
+        crate::graphql::PipelineAction,
+        crate::graphql::PipelineInfo,
+        bool,
+    )> = None;
+    let mut status_line = crate::ui::StatusLine::default();
+    let mut form: Option<crate::trigger::Form> = None;
+    let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut creating = false;
+
     loop {
         match key_rx.recv().await {
             None => return Ok(()),
             Some(event) => match event {
                 crate::events::Event::Tick => (),
+                crate::events::Event::Key(k) if confirm.is_some() => match k {
+                    termion::event::Key::Char('y') => match confirm.take() {
+                        Some((crate::graphql::PipelineAction::Destroy, pipeline, false)) => {
+                            confirm =
+                                Some((crate::graphql::PipelineAction::Destroy, pipeline, true));
+                        }
+                        Some((action, pipeline, _)) => {
+                            status_line.set(format!(
+                                "{} {} #{}...",
+                                action.ongoing(),
+                                pipeline.project_name,
+                                pipeline.pipeline_iid
+                            ));
+                            let client = clients.get(&pipeline.instance).cloned();
+                            let done_tx = done_tx.clone();
+                            tokio::spawn(async move {
+                                let res = match client {
+                                    Some(client) => {
+                                        crate::graphql::pipeline_action(&client, &pipeline, action)
+                                            .await
+                                    }
+                                    None => {
+                                        Err(anyhow::anyhow!("no client for {}", pipeline.instance))
+                                    }
+                                };
+                                let what = format!(
+                                    "{} {} #{}",
+                                    action, pipeline.project_name, pipeline.pipeline_iid
+                                );
+                                let text = match res {
+                                    Ok(errors) if errors.is_empty() => format!("{}: ok", what),
+                                    Ok(errors) => {
+                                        format!("{} failed: {}", what, errors.join(", "))
+                                    }
+                                    Err(e) => format!("{} failed: {}", what, e),
+                                };
+                                let _ = done_tx.send(Done::Action(
+                                    text,
+                                    pipeline.instance,
+                                    pipeline.project_name,
+                                ));
+                            });
+                        }
+                        None => (),
+                    },
+                    termion::event::Key::Char('n') | termion::event::Key::Esc => confirm = None,
+                    _ => (),
+                },
+                crate::events::Event::Key(k) if form.is_some() => {
+                    let event = match form.as_mut() {
+                        Some(form) => form.handle_key(k),
+                        None => crate::trigger::FormEvent::None,
+                    };
+                    match event {
+                        crate::trigger::FormEvent::None => (),
+                        crate::trigger::FormEvent::Cancel => form = None,
+                        crate::trigger::FormEvent::Submit if creating => {
+                            status_line.set("Already creating a pipeline".to_string());
+                        }
+                        crate::trigger::FormEvent::Submit => {
+                            // The form stays open on errors so the input can be fixed
+                            if let Some(form) = form.as_ref() {
+                                match submit(&clients, form, &done_tx) {
+                                    Ok(target) => {
+                                        creating = true;
+                                        status_line
+                                            .set(format!("Creating pipeline {}...", target.name));
+                                    }
+                                    Err(e) => status_line.set(e.to_string()),
+                                }
+                            }
+                        }
+                    }
+                }
                 crate::events::Event::Key(k) => match k {
                     termion::event::Key::Esc => return Ok(()),
                     termion::event::Key::Down => {
@@ -53,12 +155,19 @@ pub(crate) async fn run<B: Backend>(
                     }
                     termion::event::Key::Char('\n') => {
                         if let Some(row) = table_state.selected() {
-                            if let Some(pipeline) = pipelines.get(row) {
+                            let pipeline = pipelines.get(row);
+                            let client = pipeline.and_then(|p| clients.get(&p.instance));
+                            let project =
+                                pipeline.and_then(|p| cfg.project(&p.instance, &p.project_name));
+                            if let (Some(pipeline), Some(client), Some(project)) =
+                                (pipeline, client, project)
+                            {
                                 crate::jobs::run(
                                     terminal,
-                                    &client,
+                                    tty,
+                                    client,
                                     &mut key_rx,
-                                    &pipeline.project_name,
+                                    &project,
                                     &pipeline.pipeline_iid,
                                 )
                                 .await?;
@@ -75,21 +184,84 @@ pub(crate) async fn run<B: Backend>(
                     termion::event::Key::Char('R') => {
                         refresh = true;
                     }
+                    termion::event::Key::Char('N') => {
+                        let selected = table_state.selected().and_then(|row| pipelines.get(row));
+                        let targets = targets(&cfg, &pipelines);
+                        let target = selected
+                            .and_then(|p| {
+                                targets.iter().position(|t| {
+                                    t.instance == p.instance && t.name == p.project_name
+                                })
+                            })
+                            .unwrap_or(0);
+                        let git_ref = selected.map(|p| p.branch.clone()).unwrap_or_default();
+                        form = Some(crate::trigger::Form::new(targets, target, git_ref));
+                    }
+                    termion::event::Key::Char(c @ ('r' | 'c' | 'D')) => {
+                        if let Some(pipeline) =
+                            table_state.selected().and_then(|row| pipelines.get(row))
+                        {
+                            let action = match c {
+                                'r' => crate::graphql::PipelineAction::Retry,
+                                'c' => crate::graphql::PipelineAction::Cancel,
+                                _ => crate::graphql::PipelineAction::Destroy,
+                            };
+                            confirm = Some((action, pipeline.clone(), false));
+                        }
+                    }
                     _k => {
                         // tracing::error!(?k);
-                        ()
                     }
                 },
             },
         }
 
-        if refresh || (chrono::Local::now() - last_update) > chrono::Duration::seconds(30) {
+        while let Ok(done) = done_rx.try_recv() {
+            match done {
+                Done::Action(text, instance, name) => {
+                    status_line.set(text);
+                    if let Some(project) = cfg.project(&instance, &name) {
+                        tokio::spawn(update_project(clients.clone(), project, pipe_tx.clone()));
+                    }
+                }
+                Done::Created(target, iid) => {
+                    creating = false;
+                    match iid {
+                        // Jump to the jobs, unless the form was closed meanwhile
+                        Ok(iid) => {
+                            status_line.set(format!("Created pipeline {} #{}", target.name, iid));
+                            refresh = true;
+                            let client = clients.get(&target.instance);
+                            let project = cfg.project(&target.instance, &target.name);
+                            if let (Some(_), Some(client), Some(project)) =
+                                (form.take(), client, project)
+                            {
+                                crate::jobs::run(
+                                    terminal,
+                                    tty,
+                                    client,
+                                    &mut key_rx,
+                                    &project,
+                                    &iid,
+                                )
+                                .await?;
+                            }
+                        }
+                        Err(e) => status_line.set(format!("Create pipeline failed: {}", e)),
+                    }
+                }
+            }
+        }
+
+        if refresh
+            || (chrono::Local::now() - last_update) > chrono::Duration::seconds(REFRESH_SECONDS)
+        {
             refresh = false;
             last_update = chrono::Local::now();
-            let client = client.clone();
+            let clients = clients.clone();
             // let project_names = project.iter().map(|p| p.name.clone()).collect();
             let pipe_tx = pipe_tx.clone();
-            tokio::spawn(update_pipelines(client, projects.clone(), pipe_tx));
+            tokio::spawn(update_pipelines(clients, cfg.clone(), pipe_tx));
         }
 
         // Se if we have new pipeline jobs
@@ -118,18 +290,28 @@ pub(crate) async fn run<B: Backend>(
                     last_update.format("%b %d %H:%M:%S")
                 )))
                 .header(tui::widgets::Row::new(vec![
+                    "Instance",
                     "Project",
                     "Branch",
                     "Created At",
+                    "Duration",
+                    "SHA",
+                    "Author",
+                    "Title",
                     "URL",
                     "Status",
                 ]))
                 .widths(&[
+                    Constraint::Percentage(7),
+                    Constraint::Percentage(12),
+                    Constraint::Percentage(12),
+                    Constraint::Percentage(10),
+                    Constraint::Percentage(7),
+                    Constraint::Percentage(6),
+                    Constraint::Percentage(8),
                     Constraint::Percentage(15),
-                    Constraint::Percentage(20),
                     Constraint::Percentage(15),
-                    Constraint::Percentage(40),
-                    Constraint::Percentage(10),
+                    Constraint::Percentage(8),
                 ])
                 .highlight_style(
                     Style::default()
@@ -141,6 +323,7 @@ pub(crate) async fn run<B: Backend>(
                 .constraints(vec![
                     Constraint::Percentage(100 - help_height_percent),
                     Constraint::Percentage(help_height_percent),
+                    Constraint::Length(1),
                 ])
                 .direction(tui::layout::Direction::Vertical)
                 .split(f.size());
@@ -149,13 +332,61 @@ pub(crate) async fn run<B: Backend>(
 
             let help = tui::widgets::Paragraph::new(HELP_TEXT);
             f.render_widget(help, main_layout[1]);
+
+            status_line.render(f, main_layout[2]);
+
+            if let Some((action, pipeline, confirmed)) = &confirm {
+                let question = if *confirmed {
+                    "This can't be undone, really delete?".to_string()
+                } else {
+                    format!(
+                        "{} pipeline {} #{} ({})?",
+                        action, pipeline.project_name, pipeline.pipeline_iid, pipeline.branch
+                    )
+                };
+                crate::ui::render_confirm(f, main_layout[0], &question);
+            }
+
+            if let Some(form) = &form {
+                form.render(f, main_layout[0]);
+            }
         })?;
     }
 }
 
+/// E.g. 1h02m03s, 2m03s or 3s
+pub(crate) fn format_duration(seconds: i64) -> String {
+    let (h, m, s) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+    if h > 0 {
+        format!("{}h{:02}m{:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
 fn pipeline_to_row<'a>(pipeline: &crate::graphql::PipelineInfo) -> tui::widgets::Row<'a> {
     let project_name = Cell::from(pipeline.project_name.clone());
+
+    // GitLab only sets the duration once finished, count running pipelines up live
+    let duration = match (&pipeline.status, pipeline.started_at, pipeline.duration) {
+        (crate::graphql::PipelineStatusEnum::RUNNING, Some(started_at), _) => Cell::from(
+            format_duration((chrono::Local::now() - started_at).num_seconds().max(0)),
+        )
+        .style(Style::default().fg(tui::style::Color::Yellow)),
+        (_, _, Some(duration)) => Cell::from(format_duration(duration)),
+        (_, Some(started_at), None) => match pipeline.finished_at {
+            Some(finished_at) => Cell::from(format_duration(
+                (finished_at - started_at).num_seconds().max(0),
+            )),
+            None => Cell::from("-"),
+        },
+        _ => Cell::from("-"),
+    };
+
     tui::widgets::Row::new(vec![
+        pipeline.instance.clone().into(),
         project_name,
         pipeline.branch.clone().into(),
         pipeline
@@ -163,26 +394,146 @@ fn pipeline_to_row<'a>(pipeline: &crate::graphql::PipelineInfo) -> tui::widgets:
             .format("%b %d %H:%M:%S")
             .to_string()
             .into(),
+        duration,
+        pipeline.sha.chars().take(8).collect::<String>().into(),
+        pipeline.username.clone().unwrap_or_default().into(),
+        pipeline.commit_title.clone().unwrap_or_default().into(),
         pipeline.web_url.clone().into(),
         (&pipeline.status).into(),
     ])
 }
 
-async fn update_pipelines<'a>(
-    client: reqwest::Client,
-    projects: Vec<crate::config::Project>,
-    pipe_tx: std::sync::Arc<
-        tokio::sync::Mutex<
-            tokio::sync::watch::Sender<(
-                chrono::DateTime<chrono::Local>,
-                Vec<crate::graphql::PipelineInfo>,
-            )>,
-        >,
+type PipelinesTx = std::sync::Arc<
+    tokio::sync::Mutex<
+        tokio::sync::watch::Sender<(
+            chrono::DateTime<chrono::Local>,
+            Vec<crate::graphql::PipelineInfo>,
+        )>,
     >,
+>;
+
+/// The projects a pipeline can be created in, configured ones first
+fn targets(
+    cfg: &crate::config::Config,
+    pipelines: &[crate::graphql::PipelineInfo],
+) -> Vec<crate::trigger::Target> {
+    let mut targets: Vec<crate::trigger::Target> = cfg
+        .projects
+        .iter()
+        .map(|p| crate::trigger::Target {
+            instance: p.instance.clone(),
+            gitlab_url: p.gitlab_url.clone(),
+            name: p.name.clone(),
+        })
+        .collect();
+    for p in pipelines.iter() {
+        let target = crate::trigger::Target {
+            instance: p.instance.clone(),
+            gitlab_url: p.gitlab_url.clone(),
+            name: p.project_name.clone(),
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+/// Starts creating the pipeline described by `form` and returns where, the
+/// result is sent as Done::Created
+fn submit(
+    clients: &crate::Clients,
+    form: &crate::trigger::Form,
+    done_tx: &tokio::sync::mpsc::UnboundedSender<Done>,
+) -> anyhow::Result<crate::trigger::Target> {
+    let target = form.check()?.clone();
+    let client = clients
+        .get(&target.instance)
+        .cloned()
+        .ok_or(anyhow::anyhow!("no client for {}", target.instance))?;
+    let git_ref = form.git_ref().to_string();
+    let variables = form.variables().to_vec();
+    let done_tx = done_tx.clone();
+    let created = target.clone();
+    tokio::spawn(async move {
+        let iid = crate::trigger::create_pipeline(&client, &created, &git_ref, &variables).await;
+        let _ = done_tx.send(Done::Created(created, iid));
+    });
+    Ok(target)
+}
+
+/// Refetches the pipelines of a single project, keeping the other rows as is
+async fn update_project(
+    clients: crate::Clients,
+    project: crate::config::Project,
+    pipe_tx: PipelinesTx,
+) {
+    let client = match clients.get(&project.instance) {
+        Some(client) => client,
+        None => return,
+    };
+    let mut new_pipelines = match crate::graphql::project_pipelines(client, &project).await {
+        Ok(new_pipelines) => new_pipelines,
+        Err(e) => {
+            tracing::error!("{} - {}", project.name, e);
+            return;
+        }
+    };
+
+    let pipe_tx = pipe_tx.lock().await;
+    let mut pipelines = pipe_tx.borrow().1.clone();
+    let is_project = |p: &crate::graphql::PipelineInfo| {
+        p.instance == project.instance && p.project_name == project.name
+    };
+    let pos = pipelines
+        .iter()
+        .position(is_project)
+        .unwrap_or(pipelines.len());
+    pipelines.retain(|p| !is_project(p));
+    let pos = pos.min(pipelines.len());
+    pipelines.splice(pos..pos, new_pipelines.drain(..));
+
+    let _ = pipe_tx.send((chrono::Local::now(), pipelines));
+}
+
+/// The configured projects followed by the ones discovered in the configured groups
+async fn projects(
+    clients: &crate::Clients,
+    cfg: &crate::config::Config,
+) -> Vec<crate::config::Project> {
+    let mut projects = cfg.projects.clone();
+    for group in cfg.groups.iter() {
+        let client = match clients.get(&group.instance) {
+            Some(client) => client,
+            None => {
+                tracing::error!("{} - no client for {}", group.name, group.instance);
+                continue;
+            }
+        };
+        match crate::graphql::group_projects(client, group).await {
+            Ok(group_projects) => {
+                for project in group_projects {
+                    if !projects
+                        .iter()
+                        .any(|p| p.name == project.name && p.instance == project.instance)
+                    {
+                        projects.push(project);
+                    }
+                }
+            }
+            Err(e) => tracing::error!("{} - {}", group.name, e),
+        }
+    }
+    projects
+}
+
+async fn update_pipelines(
+    clients: crate::Clients,
+    cfg: std::sync::Arc<crate::config::Config>,
+    pipe_tx: PipelinesTx,
 ) {
     let mut pipelines = Vec::new();
-    for project in projects.iter() {
-        let new_pipelines = crate::graphql::project_pipelines(&client, &project).await;
+    for (project, new_pipelines) in fetch_pipelines(&clients, &cfg).await {
         match new_pipelines {
             Ok(mut new_pipelines) => pipelines.append(&mut new_pipelines),
             Err(e) => tracing::error!("{} - {}", project.name, e),
@@ -194,3 +545,169 @@ async fn update_pipelines<'a>(
 
     let _ = pipe_tx.send((now, pipelines));
 }
+
+/// The pipelines of all configured and discovered projects, in config order
+pub(crate) async fn fetch_pipelines(
+    clients: &crate::Clients,
+    cfg: &crate::config::Config,
+) -> Vec<(
+    crate::config::Project,
+    anyhow::Result<Vec<crate::graphql::PipelineInfo>>,
+)> {
+    use futures::StreamExt;
+
+    let projects = projects(clients, cfg).await;
+
+    let mut batched = if cfg.batch_threshold > 0 && projects.len() >= cfg.batch_threshold {
+        batched_pipelines(clients, &projects).await
+    } else {
+        std::collections::HashMap::new()
+    };
+
+    // buffered keeps the results in config order
+    let results = futures::stream::iter(projects.clone())
+        .map(|project| {
+            let client = clients.get(&project.instance).cloned();
+            let batched = batched.remove(&(project.instance.clone(), project.name.clone()));
+            async move {
+                if let Some(pipelines) = batched {
+                    return Ok(pipelines);
+                }
+                let client = client.ok_or(anyhow::anyhow!("no client for {}", project.instance))?;
+                let start = std::time::Instant::now();
+                let res = crate::graphql::project_pipelines(&client, &project).await;
+                tracing::info!(
+                    project = %project.name,
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "project_pipelines"
+                );
+                res
+            }
+        })
+        .buffered(cfg.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    projects.into_iter().zip(results).collect()
+}
+
+/// The jobs of each of `pipelines`, in the same order
+pub(crate) async fn fetch_jobs(
+    clients: &crate::Clients,
+    cfg: &crate::config::Config,
+    pipelines: Vec<crate::graphql::PipelineInfo>,
+) -> Vec<(
+    crate::graphql::PipelineInfo,
+    anyhow::Result<Vec<crate::graphql::JobInfo>>,
+)> {
+    use futures::StreamExt;
+
+    futures::stream::iter(pipelines)
+        .map(|pipeline| {
+            let client = clients.get(&pipeline.instance).cloned();
+            async move {
+                let jobs = match client {
+                    Some(client) => {
+                        crate::graphql::pipeline_jobs(
+                            &client,
+                            &pipeline.gitlab_url,
+                            &pipeline.project_name,
+                            &pipeline.pipeline_iid,
+                        )
+                        .await
+                    }
+                    None => Err(anyhow::anyhow!("no client for {}", pipeline.instance)),
+                };
+                (pipeline, jobs)
+            }
+        })
+        .buffered(cfg.concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Pipelines keyed on (instance, project name) using one ProjectsPipelines
+/// query per gitlab-url. Projects missing from the result, or in a batch that
+/// failed, are left for the per project queries.
+async fn batched_pipelines(
+    clients: &crate::Clients,
+    projects: &[crate::config::Project],
+) -> std::collections::HashMap<(String, String), Vec<crate::graphql::PipelineInfo>> {
+    let mut res = std::collections::HashMap::new();
+    for batch in batches(projects).iter() {
+        let instance = &batch[0].instance;
+        let client = match clients.get(instance) {
+            Some(client) => client,
+            None => continue,
+        };
+        let start = std::time::Instant::now();
+        match crate::graphql::projects_pipelines(client, batch).await {
+            Ok(pipelines) => {
+                tracing::info!(
+                    instance = %instance,
+                    projects = batch.len(),
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "projects_pipelines"
+                );
+                for (name, pipelines) in pipelines {
+                    res.insert((instance.clone(), name), pipelines);
+                }
+            }
+            Err(e) => tracing::warn!(
+                "{} - batched query failed, falling back to per project queries: {}",
+                instance,
+                e
+            ),
+        }
+    }
+    res
+}
+
+/// The projects grouped on instance and gitlab-url, in config order
+fn batches(projects: &[crate::config::Project]) -> Vec<Vec<crate::config::Project>> {
+    let mut batches: Vec<Vec<crate::config::Project>> = Vec::new();
+    for project in projects.iter() {
+        match batches
+            .iter_mut()
+            .find(|b| b[0].instance == project.instance && b[0].gitlab_url == project.gitlab_url)
+        {
+            Some(batch) => batch.push(project.clone()),
+            None => batches.push(vec![project.clone()]),
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn batches() {
+        let cfg = br#"
+gitlab-access-token: xxx
+instances:
+  - name: internal
+    gitlab-url: https://gitlab.internal/
+    gitlab-access-token: yyy
+projects:
+  - name: a/b
+  - name: c/d
+    instance: internal
+  - name: e/f
+    gitlab-url: https://gitlab.example.com/
+  - name: g/h
+"#;
+        let cfg = crate::config::parse_config(cfg).unwrap();
+        let batches = super::batches(&cfg.projects)
+            .iter()
+            .map(|b| b.iter().map(|p| p.name.clone()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(batches, vec![vec!["a/b", "g/h"], vec!["c/d"], vec!["e/f"]]);
+    }
+
+    #[test]
+    fn format_duration() {
+        assert_eq!(super::format_duration(3), "3s");
+        assert_eq!(super::format_duration(123), "2m03s");
+        assert_eq!(super::format_duration(3723), "1h02m03s");
+    }
+}
@@ -0,0 +1,321 @@
+//! The `serve` subcommand, a web dashboard of the pipelines and their jobs
+
+use crate::graphql::{JobInfo, PipelineInfo, PipelineStatusEnum};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// The pipelines and jobs of the last poll, shared by all viewers
+#[derive(Default)]
+struct Cache {
+    updated_at: Option<chrono::DateTime<chrono::Local>>,
+    pipelines: Vec<PipelineInfo>,
+    /// Keyed on pipeline id, with the pipeline status when they were fetched
+    jobs: HashMap<String, (PipelineStatusEnum, Vec<JobInfo>)>,
+}
+
+impl Cache {
+    fn jobs(&self, pipeline: &PipelineInfo) -> Option<&[JobInfo]> {
+        self.jobs.get(&pipeline.id).map(|(_, jobs)| jobs.as_slice())
+    }
+
+    /// Jobs of finished pipelines only change with the pipeline status
+    fn needs_jobs(&self, pipeline: &PipelineInfo) -> bool {
+        match self.jobs.get(&pipeline.id) {
+            Some((status, _)) => status != &pipeline.status || !status.is_finished(),
+            None => true,
+        }
+    }
+}
+
+type SharedCache = std::sync::Arc<tokio::sync::RwLock<Cache>>;
+
+#[derive(serde::Serialize)]
+struct ApiResponse {
+    updated_at: Option<chrono::DateTime<chrono::Local>>,
+    pipelines: Vec<crate::status::PipelineStatus>,
+}
+
+pub(crate) async fn run(
+    clients: crate::Clients,
+    cfg: std::sync::Arc<crate::config::Config>,
+    addr: std::net::SocketAddr,
+) -> anyhow::Result<()> {
+    let cache = SharedCache::default();
+
+    let poll_cache = cache.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            crate::pipelines::REFRESH_SECONDS as u64,
+        ));
+        loop {
+            interval.tick().await;
+            update(&clients, &cfg, &poll_cache).await;
+        }
+    });
+
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let cache = cache.clone();
+        async move {
+            Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |req| {
+                serve(req, cache.clone())
+            }))
+        }
+    });
+    println!("Serving the dashboard on http://{}/", addr);
+    hyper::Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn serve(
+    req: hyper::Request<hyper::Body>,
+    cache: SharedCache,
+) -> Result<hyper::Response<hyper::Body>, std::convert::Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&hyper::Method::GET, "/") => hyper::Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(hyper::Body::from(render(&*cache.read().await))),
+        (&hyper::Method::GET, "/api/pipelines") => {
+            let cache = cache.read().await;
+            let body = ApiResponse {
+                updated_at: cache.updated_at,
+                pipelines: cache
+                    .pipelines
+                    .iter()
+                    .map(|pipeline| crate::status::PipelineStatus {
+                        pipeline: pipeline.clone(),
+                        jobs: cache.jobs(pipeline).map(|jobs| jobs.to_vec()),
+                    })
+                    .collect(),
+            };
+            match serde_json::to_vec(&body) {
+                Ok(body) => hyper::Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(hyper::Body::from(body)),
+                Err(e) => hyper::Response::builder()
+                    .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(hyper::Body::from(e.to_string())),
+            }
+        }
+        _ => hyper::Response::builder()
+            .status(hyper::StatusCode::NOT_FOUND)
+            .body(hyper::Body::empty()),
+    };
+    Ok(response.expect("valid response"))
+}
+
+/// Fetches the pipelines, and the jobs of those that are running or changed
+async fn update(clients: &crate::Clients, cfg: &crate::config::Config, cache: &SharedCache) {
+    let fetched = crate::pipelines::fetch_pipelines(clients, cfg).await;
+
+    let (pipelines, stale) = {
+        let cache = cache.read().await;
+        let pipelines = merge(&cache.pipelines, fetched);
+        let stale = pipelines
+            .iter()
+            .filter(|p| cache.needs_jobs(p))
+            .cloned()
+            .collect::<Vec<_>>();
+        (pipelines, stale)
+    };
+    let jobs = crate::pipelines::fetch_jobs(clients, cfg, stale).await;
+
+    let mut cache = cache.write().await;
+    for (pipeline, jobs) in jobs {
+        match jobs {
+            Ok(jobs) => {
+                cache.jobs.insert(pipeline.id, (pipeline.status, jobs));
+            }
+            Err(e) => eprintln!(
+                "{} #{} - {}",
+                pipeline.project_name, pipeline.pipeline_iid, e
+            ),
+        }
+    }
+    let ids = pipelines
+        .iter()
+        .map(|p| p.id.clone())
+        .collect::<std::collections::HashSet<_>>();
+    cache.jobs.retain(|id, _| ids.contains(id));
+    cache.pipelines = pipelines;
+    cache.updated_at = Some(chrono::Local::now());
+}
+
+/// The fetched pipelines in config order, projects whose fetch failed keep
+/// their `cached` pipelines
+fn merge(
+    cached: &[PipelineInfo],
+    fetched: Vec<(crate::config::Project, anyhow::Result<Vec<PipelineInfo>>)>,
+) -> Vec<PipelineInfo> {
+    let mut pipelines = Vec::new();
+    for (project, new_pipelines) in fetched {
+        match new_pipelines {
+            Ok(mut new_pipelines) => pipelines.append(&mut new_pipelines),
+            Err(e) => {
+                eprintln!("{} - {}", project.name, e);
+                pipelines.extend(
+                    cached
+                        .iter()
+                        .filter(|p| {
+                            p.instance == project.instance && p.project_name == project.name
+                        })
+                        .cloned(),
+                );
+            }
+        }
+    }
+    pipelines
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 1em; }
+table { border-collapse: collapse; }
+th, td { text-align: left; padding: 0.2em 0.6em; border-bottom: 1px solid #ddd; vertical-align: top; }
+.status { font-weight: bold; }
+.SUCCESS { color: #1f7a1f; }
+.FAILED { color: #c62828; }
+.RUNNING { color: #b8860b; }
+.CANCELED, .SKIPPED { color: #777; }
+.job { display: inline-block; margin: 0 0.4em 0.2em 0; font-size: 0.85em; }
+"#;
+
+/// The pipelines as a page that reloads itself on every refresh
+fn render(cache: &Cache) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta http-equiv=\"refresh\" content=\"{}\">\n\
+         <title>Pipelines</title>\n<style>{}</style>\n</head>\n<body>\n",
+        crate::pipelines::REFRESH_SECONDS,
+        STYLE
+    );
+    match cache.updated_at {
+        Some(updated_at) => {
+            let _ = writeln!(
+                out,
+                "<p>Updated {}</p>",
+                updated_at.format("%b %d %H:%M:%S")
+            );
+        }
+        None => out.push_str("<p>Fetching pipelines...</p>\n"),
+    }
+
+    out.push_str(
+        "<table>\n<tr><th>Project</th><th>Pipeline</th><th>Branch</th><th>Status</th>\
+         <th>Created</th><th>Duration</th><th>Author</th><th>Title</th><th>Jobs</th></tr>\n",
+    );
+    for pipeline in cache.pipelines.iter() {
+        let duration = pipeline
+            .duration
+            .map(crate::pipelines::format_duration)
+            .unwrap_or_else(|| "-".to_string());
+        let _ = write!(
+            out,
+            "<tr><td>{}</td><td><a href=\"{}\">#{}</a></td><td>{}</td>\
+             <td class=\"status {:?}\">{:?}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>",
+            escape(&pipeline.project_name),
+            escape(&pipeline.web_url),
+            escape(&pipeline.pipeline_iid),
+            escape(&pipeline.branch),
+            pipeline.status,
+            pipeline.status,
+            pipeline.created_at.format("%b %d %H:%M:%S"),
+            duration,
+            escape(pipeline.username.as_deref().unwrap_or_default()),
+            escape(pipeline.commit_title.as_deref().unwrap_or_default()),
+        );
+        for job in cache.jobs(pipeline).unwrap_or_default() {
+            let _ = write!(
+                out,
+                "<span class=\"job {:?}\" title=\"{:?}\">{}/{}</span>",
+                job.status,
+                job.status,
+                escape(&job.stage_name),
+                escape(&job.name)
+            );
+        }
+        out.push_str("</td></tr>\n");
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graphql::{CiJobStatus, JobInfo, PipelineInfo, PipelineStatusEnum};
+
+    fn pipeline(status: PipelineStatusEnum) -> PipelineInfo {
+        PipelineInfo {
+            duration: Some(65),
+            username: Some("alice".to_string()),
+            commit_title: Some("Fix <script>".to_string()),
+            ..PipelineInfo::test(status)
+        }
+    }
+
+    #[test]
+    fn render() {
+        let failed = pipeline(PipelineStatusEnum::FAILED);
+        let job = JobInfo {
+            name: "unit".to_string(),
+            ..JobInfo::test("gid://gitlab/Ci::Build/3", CiJobStatus::FAILED)
+        };
+        let mut cache = super::Cache {
+            updated_at: None,
+            pipelines: vec![failed.clone()],
+            jobs: vec![(failed.id.clone(), (failed.status.clone(), vec![job]))]
+                .into_iter()
+                .collect(),
+        };
+
+        let html = super::render(&cache);
+        assert!(html.contains("<meta http-equiv=\"refresh\" content=\"30\">"));
+        assert!(html.contains("<a href=\"https://gitlab.com/a/b/-/pipelines/1\">#7</a>"));
+        assert!(html.contains("<td class=\"status FAILED\">FAILED</td>"));
+        assert!(html.contains("<td>1m05s</td>"));
+        assert!(html.contains("Fix &lt;script&gt;"));
+        assert!(html.contains("<span class=\"job FAILED\" title=\"FAILED\">test/unit</span>"));
+
+        assert!(!cache.needs_jobs(&failed));
+        assert!(cache.needs_jobs(&pipeline(PipelineStatusEnum::RUNNING)));
+        cache.jobs.clear();
+        assert!(cache.needs_jobs(&failed));
+    }
+
+    #[test]
+    fn merge() {
+        let cfg = br#"
+gitlab-access-token: xxx
+projects:
+  - name: a/b
+  - name: c/d
+"#;
+        let cfg = crate::config::parse_config(cfg).unwrap();
+        let cached = vec![
+            pipeline(PipelineStatusEnum::RUNNING),
+            PipelineInfo {
+                project_name: "c/d".to_string(),
+                ..pipeline(PipelineStatusEnum::RUNNING)
+            },
+        ];
+        let fetched = vec![
+            (
+                cfg.projects[0].clone(),
+                Err(anyhow::anyhow!("502 Bad Gateway")),
+            ),
+            (cfg.projects[1].clone(), Ok(Vec::new())),
+        ];
+
+        // The failed a/b keeps its row, c/d has no pipelines any more
+        let pipelines = super::merge(&cached, fetched);
+        assert_eq!(pipelines.len(), 1);
+        assert_eq!(pipelines[0].project_name, "a/b");
+    }
+}
@@ -0,0 +1,291 @@
+//! The `status` subcommand, prints the pipelines once instead of running the TUI
+
+use std::io::Write;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Table,
+    Json,
+    Yaml,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(anyhow::anyhow!("Unknown format {}", s)),
+        }
+    }
+}
+
+/// A pipeline, with its jobs if asked for
+#[derive(serde::Serialize)]
+pub(crate) struct PipelineStatus {
+    #[serde(flatten)]
+    pub pipeline: crate::graphql::PipelineInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<Vec<crate::graphql::JobInfo>>,
+}
+
+pub(crate) async fn run(
+    clients: &crate::Clients,
+    cfg: &crate::config::Config,
+    format: Format,
+    with_jobs: bool,
+) -> anyhow::Result<()> {
+    let mut failed = 0;
+    let mut statuses = Vec::new();
+    for (project, pipelines) in crate::pipelines::fetch_pipelines(clients, cfg).await {
+        match pipelines {
+            Ok(pipelines) => {
+                statuses.extend(pipelines.into_iter().map(|pipeline| PipelineStatus {
+                    pipeline,
+                    jobs: None,
+                }))
+            }
+            Err(e) => {
+                eprintln!("{} - {}", project.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if with_jobs {
+        for status in statuses.iter_mut() {
+            let pipeline = &status.pipeline;
+            let client = clients
+                .get(&pipeline.instance)
+                .ok_or(anyhow::anyhow!("no client for {}", pipeline.instance))?;
+            let jobs = crate::graphql::pipeline_jobs(
+                client,
+                &pipeline.gitlab_url,
+                &pipeline.project_name,
+                &pipeline.pipeline_iid,
+            )
+            .await;
+            match jobs {
+                Ok(jobs) => status.jobs = Some(jobs),
+                Err(e) => {
+                    eprintln!(
+                        "{} #{} - {}",
+                        pipeline.project_name, pipeline.pipeline_iid, e
+                    );
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Table => write_table(&mut out, &statuses)?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &statuses)?;
+            writeln!(out)?;
+        }
+        Format::Yaml => serde_yaml::to_writer(&mut out, &statuses)?,
+        Format::Csv => write_csv(&mut out, &statuses)?,
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} fetches failed", failed);
+    }
+    Ok(())
+}
+
+fn duration(duration: Option<i64>) -> String {
+    duration
+        .map(crate::pipelines::format_duration)
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Columns padded to the widest cell, jobs indented under their pipeline
+fn write_table(out: &mut impl Write, statuses: &[PipelineStatus]) -> std::io::Result<()> {
+    let mut rows = vec![[
+        "PROJECT", "IID", "BRANCH", "STATUS", "CREATED", "DURATION", "AUTHOR", "URL",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect::<Vec<_>>()];
+
+    for status in statuses {
+        let p = &status.pipeline;
+        rows.push(vec![
+            p.project_name.clone(),
+            p.pipeline_iid.clone(),
+            p.branch.clone(),
+            format!("{:?}", p.status),
+            p.created_at.format("%b %d %H:%M:%S").to_string(),
+            duration(p.duration),
+            p.username.clone().unwrap_or_default(),
+            p.web_url.clone(),
+        ]);
+        for job in status.jobs.iter().flatten() {
+            rows.push(vec![
+                format!("  {}", job.name),
+                String::new(),
+                job.stage_name.clone(),
+                format!("{:?}", job.status),
+                String::new(),
+                duration(job.duration),
+                String::new(),
+                String::new(),
+            ]);
+        }
+    }
+
+    let mut widths = vec![0; rows[0].len()];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.width());
+        }
+    }
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell.width())))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// One row per pipeline, or per job with the pipeline columns repeated
+fn write_csv(out: &mut impl Write, statuses: &[PipelineStatus]) -> std::io::Result<()> {
+    let with_jobs = statuses.iter().any(|s| s.jobs.is_some());
+    let mut header = vec![
+        "instance",
+        "project",
+        "iid",
+        "branch",
+        "status",
+        "created_at",
+        "duration",
+        "sha",
+        "username",
+        "title",
+        "url",
+    ];
+    if with_jobs {
+        header.extend(["job_name", "job_stage", "job_status", "job_duration"]);
+    }
+    writeln!(out, "{}", header.join(","))?;
+
+    for status in statuses {
+        let p = &status.pipeline;
+        let pipeline = vec![
+            p.instance.clone(),
+            p.project_name.clone(),
+            p.pipeline_iid.clone(),
+            p.branch.clone(),
+            format!("{:?}", p.status),
+            p.created_at.to_rfc3339(),
+            p.duration.map(|d| d.to_string()).unwrap_or_default(),
+            p.sha.clone(),
+            p.username.clone().unwrap_or_default(),
+            p.commit_title.clone().unwrap_or_default(),
+            p.web_url.clone(),
+        ];
+        let jobs = status.jobs.as_deref().unwrap_or_default();
+        if !with_jobs || jobs.is_empty() {
+            let mut row = pipeline.clone();
+            if with_jobs {
+                row.extend(vec![String::new(); 4]);
+            }
+            writeln!(out, "{}", csv_row(&row))?;
+        }
+        for job in jobs {
+            let mut row = pipeline.clone();
+            row.extend(vec![
+                job.name.clone(),
+                job.stage_name.clone(),
+                format!("{:?}", job.status),
+                job.duration.map(|d| d.to_string()).unwrap_or_default(),
+            ]);
+            writeln!(out, "{}", csv_row(&row))?;
+        }
+    }
+    Ok(())
+}
+
+fn csv_row(row: &[String]) -> String {
+    row.iter()
+        .map(|f| csv_field(f))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graphql::{CiJobStatus, JobInfo, PipelineInfo, PipelineStatusEnum};
+
+    fn status() -> super::PipelineStatus {
+        super::PipelineStatus {
+            pipeline: PipelineInfo {
+                duration: Some(65),
+                username: Some("alice".to_string()),
+                commit_title: Some("Fix \"x\", y".to_string()),
+                ..PipelineInfo::test(PipelineStatusEnum::FAILED)
+            },
+            jobs: Some(vec![JobInfo {
+                name: "unit".to_string(),
+                ..JobInfo::test("gid://gitlab/Ci::Build/3", CiJobStatus::FAILED)
+            }]),
+        }
+    }
+
+    #[test]
+    fn table() {
+        let mut out = Vec::new();
+        super::write_table(&mut out, &[status()]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("PROJECT  IID  BRANCH  STATUS  CREATED"));
+        assert!(lines[1].starts_with("a/b      7    master  FAILED  Oct 01 12:00:00  1m05s"));
+        assert_eq!(lines[2], "  unit        test    FAILED                   -");
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = Vec::new();
+        super::write_csv(&mut out, &[status()]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(",url,job_name,job_stage,job_status,job_duration"));
+        assert!(lines[1].contains(",alice,\"Fix \"\"x\"\", y\",https://"));
+        assert!(lines[1].ends_with(",unit,test,FAILED,"));
+    }
+
+    #[test]
+    fn json() {
+        let mut status = status();
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["project_name"], "a/b");
+        assert_eq!(json["status"], "FAILED");
+        assert_eq!(json["jobs"][0]["name"], "unit");
+
+        status.jobs = None;
+        let json = serde_json::to_value(&status).unwrap();
+        assert!(json.get("jobs").is_none());
+    }
+}
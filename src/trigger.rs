@@ -0,0 +1,264 @@
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+/// A project a pipeline can be created in
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Target {
+    pub instance: String,
+    pub gitlab_url: String,
+    pub name: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Project,
+    Ref,
+    Variables,
+}
+
+pub(crate) enum FormEvent {
+    None,
+    Cancel,
+    Submit,
+}
+
+/// Form for creating a pipeline, shown on top of the pipelines view
+pub(crate) struct Form {
+    targets: Vec<Target>,
+    target: usize,
+    git_ref: String,
+    variables: Vec<(String, String)>,
+    /// The variable being typed, KEY=value
+    variable: String,
+    field: Field,
+}
+
+impl Form {
+    pub(crate) fn new(targets: Vec<Target>, target: usize, git_ref: String) -> Self {
+        Self {
+            target: target.min(targets.len().saturating_sub(1)),
+            targets,
+            git_ref,
+            variables: Vec::new(),
+            variable: String::new(),
+            field: Field::Project,
+        }
+    }
+
+    pub(crate) fn target(&self) -> Option<&Target> {
+        self.targets.get(self.target)
+    }
+
+    /// The target, unless there is none or a variable is typed but not added
+    pub(crate) fn check(&self) -> anyhow::Result<&Target> {
+        if !self.variable.is_empty() {
+            anyhow::bail!(
+                "Variable {} is not added, press Enter in Variables to add it",
+                self.variable
+            );
+        }
+        self.target()
+            .ok_or(anyhow::anyhow!("No project to create a pipeline in"))
+    }
+
+    pub(crate) fn git_ref(&self) -> &str {
+        &self.git_ref
+    }
+
+    pub(crate) fn variables(&self) -> &[(String, String)] {
+        &self.variables
+    }
+
+    pub(crate) fn handle_key(&mut self, key: termion::event::Key) -> FormEvent {
+        use termion::event::Key;
+        match key {
+            Key::Esc => return FormEvent::Cancel,
+            Key::Char('\t') | Key::Down => {
+                self.field = match self.field {
+                    Field::Project => Field::Ref,
+                    Field::Ref => Field::Variables,
+                    Field::Variables => Field::Project,
+                }
+            }
+            Key::BackTab | Key::Up => {
+                self.field = match self.field {
+                    Field::Project => Field::Variables,
+                    Field::Ref => Field::Project,
+                    Field::Variables => Field::Ref,
+                }
+            }
+            Key::Char('\n') => {
+                if self.field != Field::Variables || self.variable.is_empty() {
+                    return FormEvent::Submit;
+                }
+                if let Some((k, v)) = self.variable.split_once('=') {
+                    if !k.trim().is_empty() {
+                        self.variables.push((k.trim().to_string(), v.to_string()));
+                        self.variable.clear();
+                    }
+                }
+            }
+            Key::Left if self.field == Field::Project && !self.targets.is_empty() => {
+                self.target = (self.target + self.targets.len() - 1) % self.targets.len();
+            }
+            Key::Right if self.field == Field::Project && !self.targets.is_empty() => {
+                self.target = (self.target + 1) % self.targets.len();
+            }
+            Key::Backspace => match self.field {
+                Field::Project => (),
+                Field::Ref => {
+                    self.git_ref.pop();
+                }
+                Field::Variables => {
+                    if self.variable.pop().is_none() {
+                        self.variables.pop();
+                    }
+                }
+            },
+            Key::Char(c) => match self.field {
+                Field::Project => (),
+                Field::Ref => self.git_ref.push(c),
+                Field::Variables => self.variable.push(c),
+            },
+            _ => (),
+        }
+        FormEvent::None
+    }
+
+    pub(crate) fn render<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let area = crate::ui::centered_rect(60, 50, area);
+
+        let label = |field: Field, text: &'static str| {
+            if self.field == field {
+                Span::styled(text, Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                Span::raw(text)
+            }
+        };
+        let project = self
+            .target()
+            .map(|t| format!("< {} ({}) >", t.name, t.instance))
+            .unwrap_or_default();
+
+        let mut lines = vec![
+            Spans::from(vec![
+                label(Field::Project, "Project:"),
+                Span::raw(" "),
+                Span::raw(project),
+            ]),
+            Spans::from(vec![
+                label(Field::Ref, "Ref:"),
+                Span::raw("     "),
+                Span::raw(self.git_ref.clone()),
+            ]),
+            Spans::from(label(Field::Variables, "Variables:")),
+        ];
+        for (k, v) in self.variables.iter() {
+            lines.push(Spans::from(format!("  {}={}", k, v)));
+        }
+        lines.push(Spans::from(format!("  > {}", self.variable)));
+        lines.push(Spans::from(""));
+        lines.push(Spans::from(
+            "Tab: next field, Left/Right: project, Enter: add KEY=value variable / create, Esc: cancel",
+        ));
+
+        let form = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL).title("New pipeline"))
+            .wrap(tui::widgets::Wrap { trim: false });
+        f.render_widget(Clear, area);
+        f.render_widget(form, area);
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Variable<'a> {
+    key: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct CreatePipeline<'a> {
+    #[serde(rename = "ref")]
+    git_ref: &'a str,
+    variables: Vec<Variable<'a>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CreatedPipeline {
+    iid: Option<u64>,
+    web_url: Option<String>,
+}
+
+/// Creates a pipeline through the REST API, returning its iid
+pub(crate) async fn create_pipeline(
+    client: &reqwest::Client,
+    target: &Target,
+    git_ref: &str,
+    variables: &[(String, String)],
+) -> anyhow::Result<String> {
+    let uri = format!(
+        "{}/projects/{}/pipeline",
+        crate::api_url(&target.gitlab_url),
+        target.name.replace('/', "%2F")
+    );
+    let body = CreatePipeline {
+        git_ref,
+        variables: variables
+            .iter()
+            .map(|(key, value)| Variable { key, value })
+            .collect(),
+    };
+
+    let response = client.post(&uri).json(&body).send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        anyhow::bail!("{} {}", status, text);
+    }
+    let created: CreatedPipeline = response.json().await?;
+    tracing::info!(?created);
+
+    created
+        .iid
+        .map(|iid| iid.to_string())
+        .ok_or(anyhow::anyhow!(
+            "No iid for the created pipeline ({})",
+            created.web_url.unwrap_or_default()
+        ))
+}
+
+#[cfg(test)]
+mod test {
+    use termion::event::Key;
+
+    #[test]
+    fn check() {
+        let target = super::Target {
+            instance: "gitlab".to_string(),
+            gitlab_url: "https://gitlab.com".to_string(),
+            name: "a/b".to_string(),
+        };
+        let form = super::Form::new(Vec::new(), 0, "master".to_string());
+        assert!(form.check().is_err());
+
+        let mut form = super::Form::new(vec![target], 0, "master".to_string());
+        form.handle_key(Key::Char('\t'));
+        form.handle_key(Key::Char('\t'));
+        for c in "A=1".chars() {
+            form.handle_key(Key::Char(c));
+        }
+        form.handle_key(Key::Up);
+        assert!(form.check().is_err());
+
+        form.handle_key(Key::Down);
+        form.handle_key(Key::Char('\n'));
+        assert_eq!(form.check().unwrap().name, "a/b");
+        assert_eq!(form.variables(), &[("A".to_string(), "1".to_string())]);
+    }
+}
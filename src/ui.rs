@@ -0,0 +1,103 @@
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
+    Frame,
+};
+
+/// A rect of `width` x `height` percent of `r`, centered in `r`
+pub(crate) fn centered_rect(width: u16, height: u16, r: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Percentage((100 - height) / 2),
+            Constraint::Percentage(height),
+            Constraint::Percentage((100 - height) / 2),
+        ])
+        .split(r);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Percentage((100 - width) / 2),
+            Constraint::Percentage(width),
+            Constraint::Percentage((100 - width) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+/// Draws a y/n question on top of whatever is in `area`
+pub(crate) fn render_confirm<B: Backend>(f: &mut Frame<B>, area: Rect, question: &str) {
+    let area = centered_rect(50, 20, area);
+    let text = format!("{}\n\n(y/n)", question);
+    let popup = Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).title("Confirm"))
+        .wrap(Wrap { trim: false });
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// The result of the last action, if any
+#[derive(Default)]
+pub(crate) struct StatusLine {
+    text: Option<(chrono::DateTime<chrono::Local>, String)>,
+}
+
+impl StatusLine {
+    pub(crate) fn set(&mut self, text: String) {
+        self.text = Some((chrono::Local::now(), text));
+    }
+
+    pub(crate) fn render<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        if let Some((at, text)) = &self.text {
+            let line = format!("{} {}", at.format("%H:%M:%S"), text);
+            f.render_widget(Paragraph::new(line), area);
+        }
+    }
+}
+
+/// Raw mode for the TUI, the terminal can be handed over to other programs
+pub(crate) struct Tty {
+    raw: termion::raw::RawTerminal<std::io::Stdout>,
+}
+
+impl Tty {
+    pub(crate) fn new() -> std::io::Result<Self> {
+        use termion::raw::IntoRawMode;
+        Ok(Self {
+            raw: std::io::stdout().into_raw_mode()?,
+        })
+    }
+
+    /// Runs `command` on the main screen, in cooked mode and with key reading
+    /// paused. The TUI needs a full redraw afterwards.
+    pub(crate) fn run_external(
+        &self,
+        command: &mut std::process::Command,
+    ) -> anyhow::Result<std::process::ExitStatus> {
+        use std::io::Write;
+
+        let _paused = crate::events::pause();
+        let mut stdout = std::io::stdout();
+        write!(
+            stdout,
+            "{}{}",
+            termion::screen::ToMainScreen,
+            termion::cursor::Show
+        )?;
+        stdout.flush()?;
+        self.raw.suspend_raw_mode()?;
+
+        let status = command.status();
+
+        self.raw.activate_raw_mode()?;
+        write!(
+            stdout,
+            "{}{}",
+            termion::screen::ToAlternateScreen,
+            termion::cursor::Hide
+        )?;
+        stdout.flush()?;
+        Ok(status?)
+    }
+}
@@ -0,0 +1,143 @@
+//! The `wait` subcommand, blocks until a pipeline has finished
+
+use crate::graphql::{CiJobStatus, JobInfo, PipelineStatusEnum};
+use std::collections::HashMap;
+
+/// The pipeline failed, was canceled or skipped, or waits for a manual job
+pub(crate) const EXIT_FAILED: i32 = 2;
+/// The pipeline was still running when the timeout expired
+pub(crate) const EXIT_TIMEOUT: i32 = 3;
+/// The project, ref or pipeline couldn't be found
+pub(crate) const EXIT_UNRESOLVED: i32 = 4;
+
+const MIN_POLL: std::time::Duration = std::time::Duration::from_secs(5);
+const MAX_POLL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Waits for the pipeline with IID `pipeline`, or the latest one on the ref
+/// `pipeline` if it isn't a number. Returns the exit code. Failing to find
+/// the pipeline is EXIT_UNRESOLVED, once it has been fetched API errors are
+/// retried and if they persist the result is EXIT_TIMEOUT.
+pub(crate) async fn run(
+    client: &reqwest::Client,
+    project: &crate::config::Project,
+    pipeline: &str,
+    timeout: std::time::Duration,
+) -> anyhow::Result<i32> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let iid = if pipeline.chars().all(|c| c.is_ascii_digit()) {
+        pipeline.to_string()
+    } else {
+        match crate::graphql::latest_pipeline_iid(
+            client,
+            &project.gitlab_url,
+            &project.name,
+            pipeline,
+        )
+        .await
+        {
+            Ok(iid) => iid,
+            Err(e) => {
+                eprintln!("{} - {}", project.name, e);
+                return Ok(EXIT_UNRESOLVED);
+            }
+        }
+    };
+    println!("Waiting for {} #{}", project.name, iid);
+
+    let mut poll = MIN_POLL;
+    let mut last_status = None;
+    let mut job_statuses = HashMap::new();
+    loop {
+        // The last poll is at the deadline
+        let at_deadline = tokio::time::Instant::now() >= deadline;
+
+        let mut changes = Vec::new();
+        match crate::graphql::pipeline_status(client, &project.gitlab_url, &project.name, &iid)
+            .await
+        {
+            Ok((status, jobs)) => {
+                changes = transitions(&job_statuses, &jobs);
+                if last_status.as_ref() != Some(&status) {
+                    changes.push(format!("pipeline #{}: {:?}", iid, status));
+                }
+                for change in changes.iter() {
+                    println!("{} {}", chrono::Local::now().format("%H:%M:%S"), change);
+                }
+                job_statuses = jobs
+                    .iter()
+                    .map(|j| (j.id.clone(), j.status.clone()))
+                    .collect();
+
+                if status == PipelineStatusEnum::SUCCESS {
+                    return Ok(0);
+                } else if status.is_finished() {
+                    return Ok(EXIT_FAILED);
+                }
+                last_status = Some(status);
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} {} - {}",
+                    chrono::Local::now().format("%H:%M:%S"),
+                    project.name,
+                    e
+                );
+                // The pipeline was never found, so there is nothing to wait for
+                if last_status.is_none() {
+                    return Ok(EXIT_UNRESOLVED);
+                }
+            }
+        }
+
+        if at_deadline {
+            eprintln!("Timed out waiting for {} {}", project.name, pipeline);
+            return Ok(EXIT_TIMEOUT);
+        }
+
+        // Back off while nothing happens, or on errors
+        poll = if changes.is_empty() {
+            (poll * 3 / 2).min(MAX_POLL)
+        } else {
+            MIN_POLL
+        };
+        tokio::time::sleep_until((tokio::time::Instant::now() + poll).min(deadline)).await;
+    }
+}
+
+/// A line per job that is new or has changed status since `before`
+fn transitions(before: &HashMap<String, CiJobStatus>, jobs: &[JobInfo]) -> Vec<String> {
+    jobs.iter()
+        .filter_map(|job| match before.get(&job.id) {
+            Some(status) if status == &job.status => None,
+            Some(status) => Some(format!(
+                "{}/{}: {:?} -> {:?}",
+                job.stage_name, job.name, status, job.status
+            )),
+            None => Some(format!("{}/{}: {:?}", job.stage_name, job.name, job.status)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graphql::{CiJobStatus, JobInfo};
+
+    #[test]
+    fn transitions() {
+        let before = vec![
+            ("1".to_string(), CiJobStatus::RUNNING),
+            ("2".to_string(), CiJobStatus::PENDING),
+        ]
+        .into_iter()
+        .collect();
+        let jobs = vec![
+            JobInfo::test("1", CiJobStatus::SUCCESS),
+            JobInfo::test("2", CiJobStatus::PENDING),
+            JobInfo::test("3", CiJobStatus::CREATED),
+        ];
+        assert_eq!(
+            super::transitions(&before, &jobs),
+            vec!["test/job-1: RUNNING -> SUCCESS", "test/job-3: CREATED",]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! The `watch` subcommand, prints a line whenever a pipeline or job changes status
+
+use crate::graphql::{CiJobStatus, JobInfo, PipelineInfo, PipelineStatusEnum};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Text,
+    Jsonl,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(anyhow::anyhow!("Unknown format {}", s)),
+        }
+    }
+}
+
+/// A pipeline or, if `job` is set, a job that is new or has changed status
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Change {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub instance: String,
+    pub project: String,
+    pub pipeline_iid: String,
+    pub branch: String,
+    pub web_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    /// stage/name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    /// Not set for new pipelines and jobs
+    pub from: Option<String>,
+    pub to: String,
+}
+
+impl Change {
+    fn text(&self) -> String {
+        let subject = match &self.job {
+            Some(job) => format!(
+                "{} #{} {} {}",
+                self.project, self.pipeline_iid, self.branch, job
+            ),
+            None => format!("{} #{} {}", self.project, self.pipeline_iid, self.branch),
+        };
+        match &self.from {
+            Some(from) => format!(
+                "{} {}: {} -> {}",
+                self.time.format("%H:%M:%S"),
+                subject,
+                from,
+                self.to
+            ),
+            None => format!("{} {}: {}", self.time.format("%H:%M:%S"), subject, self.to),
+        }
+    }
+}
+
+/// instance, project name and pipeline IID
+type Key = (String, String, String);
+
+fn key(pipeline: &PipelineInfo) -> Key {
+    (
+        pipeline.instance.clone(),
+        pipeline.project_name.clone(),
+        pipeline.pipeline_iid.clone(),
+    )
+}
+
+/// The statuses printed last, to diff the next poll against
+#[derive(Default)]
+struct State {
+    pipelines: HashMap<Key, PipelineStatusEnum>,
+    /// Keyed on job id
+    jobs: HashMap<Key, HashMap<String, CiJobStatus>>,
+}
+
+impl State {
+    /// Jobs are fetched for running pipelines, and for pipelines that just
+    /// finished to catch the last job transitions. Not for pipelines that had
+    /// already finished when watching started.
+    fn needs_jobs(&self, pipeline: &PipelineInfo) -> bool {
+        match self.pipelines.get(&key(pipeline)) {
+            _ if !pipeline.status.is_finished() => true,
+            Some(status) => status != &pipeline.status,
+            None => false,
+        }
+    }
+
+    /// Records `pipeline`, and `jobs` if they were fetched, returning what changed
+    fn update(
+        &mut self,
+        pipeline: &PipelineInfo,
+        jobs: Option<&[JobInfo]>,
+        time: chrono::DateTime<chrono::Local>,
+    ) -> Vec<Change> {
+        let key = key(pipeline);
+        let change = |job: Option<&JobInfo>, from: Option<String>, to: String| Change {
+            time,
+            instance: pipeline.instance.clone(),
+            project: pipeline.project_name.clone(),
+            pipeline_iid: pipeline.pipeline_iid.clone(),
+            branch: pipeline.branch.clone(),
+            web_url: pipeline.web_url.clone(),
+            job_id: job.map(|j| j.id.clone()),
+            job: job.map(|j| format!("{}/{}", j.stage_name, j.name)),
+            from,
+            to,
+        };
+
+        let mut changes = Vec::new();
+        let before = self.pipelines.insert(key.clone(), pipeline.status.clone());
+        if before.as_ref() != Some(&pipeline.status) {
+            changes.push(change(
+                None,
+                before.map(|s| format!("{:?}", s)),
+                format!("{:?}", pipeline.status),
+            ));
+        }
+
+        if let Some(jobs) = jobs {
+            let before = self.jobs.remove(&key).unwrap_or_default();
+            for job in jobs {
+                match before.get(&job.id) {
+                    Some(status) if status == &job.status => (),
+                    status => changes.push(change(
+                        Some(job),
+                        status.map(|s| format!("{:?}", s)),
+                        format!("{:?}", job.status),
+                    )),
+                }
+            }
+            self.jobs.insert(
+                key,
+                jobs.iter()
+                    .map(|j| (j.id.clone(), j.status.clone()))
+                    .collect(),
+            );
+        }
+        changes
+    }
+
+    /// Forgets pipelines no longer listed, except those of the projects,
+    /// by instance and name, whose fetch `failed`
+    fn retain(&mut self, pipelines: &[PipelineInfo], failed: &HashSet<(String, String)>) {
+        let keys = pipelines.iter().map(key).collect::<HashSet<_>>();
+        let keep = |k: &Key| keys.contains(k) || failed.contains(&(k.0.clone(), k.1.clone()));
+        self.pipelines.retain(|k, _| keep(k));
+        self.jobs.retain(|k, _| keep(k));
+    }
+}
+
+pub(crate) async fn run(
+    clients: &crate::Clients,
+    cfg: &crate::config::Config,
+    format: Format,
+) -> anyhow::Result<()> {
+    let mut state = State::default();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        crate::pipelines::REFRESH_SECONDS as u64,
+    ));
+    loop {
+        interval.tick().await;
+
+        let mut pipelines = Vec::new();
+        let mut failed = HashSet::new();
+        for (project, new_pipelines) in crate::pipelines::fetch_pipelines(clients, cfg).await {
+            match new_pipelines {
+                Ok(mut new_pipelines) => pipelines.append(&mut new_pipelines),
+                Err(e) => {
+                    eprintln!("{} - {}", project.name, e);
+                    failed.insert((project.instance, project.name));
+                }
+            }
+        }
+
+        let stale = pipelines
+            .iter()
+            .filter(|p| state.needs_jobs(p))
+            .cloned()
+            .collect();
+        let mut jobs = HashMap::new();
+        for (pipeline, fetched) in crate::pipelines::fetch_jobs(clients, cfg, stale).await {
+            match fetched {
+                Ok(fetched) => {
+                    jobs.insert(key(&pipeline), fetched);
+                }
+                Err(e) => eprintln!(
+                    "{} #{} - {}",
+                    pipeline.project_name, pipeline.pipeline_iid, e
+                ),
+            }
+        }
+
+        let now = chrono::Local::now();
+        for pipeline in pipelines.iter() {
+            let jobs = jobs.get(&key(pipeline)).map(Vec::as_slice);
+            for change in state.update(pipeline, jobs, now) {
+                match format {
+                    Format::Text => println!("{}", change.text()),
+                    Format::Jsonl => println!("{}", serde_json::to_string(&change)?),
+                }
+            }
+        }
+        state.retain(&pipelines, &failed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graphql::{CiJobStatus, JobInfo, PipelineInfo, PipelineStatusEnum};
+
+    #[test]
+    fn update() {
+        use chrono::TimeZone;
+        let now = chrono::Local
+            .with_ymd_and_hms(2021, 10, 1, 12, 30, 0)
+            .unwrap();
+        let mut state = super::State::default();
+
+        let running = PipelineInfo::test(PipelineStatusEnum::RUNNING);
+        assert!(state.needs_jobs(&running));
+        let jobs = vec![JobInfo::test("1", CiJobStatus::RUNNING)];
+        let changes = state.update(&running, Some(&jobs), now);
+        assert_eq!(
+            changes.iter().map(|c| c.text()).collect::<Vec<_>>(),
+            vec![
+                "12:30:00 a/b #7 master: RUNNING",
+                "12:30:00 a/b #7 master test/job-1: RUNNING"
+            ]
+        );
+        assert!(state.update(&running, Some(&jobs), now).is_empty());
+
+        let success = PipelineInfo::test(PipelineStatusEnum::SUCCESS);
+        assert!(state.needs_jobs(&success));
+        let jobs = vec![JobInfo::test("1", CiJobStatus::SUCCESS)];
+        let changes = state.update(&success, Some(&jobs), now);
+        assert_eq!(
+            changes.iter().map(|c| c.text()).collect::<Vec<_>>(),
+            vec![
+                "12:30:00 a/b #7 master: RUNNING -> SUCCESS",
+                "12:30:00 a/b #7 master test/job-1: RUNNING -> SUCCESS"
+            ]
+        );
+        assert!(!state.needs_jobs(&success));
+
+        let json = serde_json::to_value(&changes[1]).unwrap();
+        assert_eq!(json["pipeline_iid"], "7");
+        assert_eq!(json["job_id"], "1");
+        assert_eq!(json["from"], "RUNNING");
+        assert_eq!(json["to"], "SUCCESS");
+
+        state.retain(&[], &Default::default());
+        assert!(!state.needs_jobs(&success));
+        assert_eq!(state.update(&success, None, now).len(), 1);
+    }
+
+    #[test]
+    fn failed_fetch() {
+        use chrono::TimeZone;
+        let now = chrono::Local
+            .with_ymd_and_hms(2021, 10, 1, 12, 30, 0)
+            .unwrap();
+        let mut state = super::State::default();
+        let running = PipelineInfo::test(PipelineStatusEnum::RUNNING);
+        let jobs = vec![JobInfo::test("1", CiJobStatus::RUNNING)];
+        state.update(&running, Some(&jobs), now);
+
+        // a/b is missing from a poll where its fetch failed, but not forgotten
+        let failed = vec![("gitlab".to_string(), "a/b".to_string())]
+            .into_iter()
+            .collect();
+        state.retain(&[], &failed);
+        assert!(state.update(&running, Some(&jobs), now).is_empty());
+    }
+}